@@ -0,0 +1,302 @@
+use crate::field::{BoardFinishedEvent, MowResult};
+use crate::harvestor::{KeyboardInput, NextBoardEvent};
+use crate::level::{ActiveLevel, Level};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::path::PathBuf;
+
+pub struct CampaignPlugin;
+
+impl Plugin for CampaignPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Campaign>()
+            .add_startup_system(start_campaign)
+            .add_system(record_campaign_result)
+            .add_system(next_campaign_level.after(KeyboardInput));
+    }
+}
+
+pub struct LevelPack {
+    pub name: &'static str,
+    pub levels: &'static [&'static str],
+}
+
+/// Played in order, a level unlocks once every level before it is harvested perfectly
+pub const LEVEL_PACKS: &[LevelPack] = &[LevelPack {
+    name: "Basics",
+    levels: &[
+        "levels/straight.level",
+        "levels/corner.level",
+        "levels/rocks.level",
+        "levels/zigzag.level",
+    ],
+}];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelScore {
+    pub stars: u8,
+    pub command_count: usize,
+}
+
+impl LevelScore {
+    fn is_better_than(&self, other: &LevelScore) -> bool {
+        self.stars > other.stars
+            || (self.stars == other.stars && self.command_count < other.command_count)
+    }
+}
+
+/// Best score of every completed level, saved as lines of `<level path> <stars> <commands>`
+#[derive(Default, Debug, PartialEq)]
+pub struct CampaignProgress {
+    pub best: HashMap<String, LevelScore>,
+}
+
+impl CampaignProgress {
+    fn parse(text: &str) -> Self {
+        let best = text
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let level = parts.next()?;
+                let stars = parts.next()?.parse().ok()?;
+                let command_count = parts.next()?.parse().ok()?;
+
+                Some((
+                    level.to_string(),
+                    LevelScore {
+                        stars,
+                        command_count,
+                    },
+                ))
+            })
+            .collect();
+
+        CampaignProgress { best }
+    }
+
+    fn to_text(&self) -> String {
+        let mut lines = self
+            .best
+            .iter()
+            .map(|(level, score)| format!("{} {} {}\n", level, score.stars, score.command_count))
+            .collect::<Vec<_>>();
+        lines.sort();
+
+        lines.concat()
+    }
+
+    /// Keeps the score if it beats the best one for this level
+    fn record(&mut self, level: &str, score: LevelScore) -> bool {
+        match self.best.get(level) {
+            Some(best) if !score.is_better_than(best) => false,
+            _ => {
+                self.best.insert(level.to_string(), score);
+                true
+            }
+        }
+    }
+
+    fn path() -> Option<PathBuf> {
+        let data_dir = if cfg!(target_os = "windows") {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            std::env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join("Library/Application Support"))
+        } else {
+            std::env::var_os("XDG_DATA_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+                })
+        };
+
+        data_dir.map(|dir| dir.join("harvestor").join("progress.txt"))
+    }
+
+    fn load() -> Self {
+        Self::path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|text| Self::parse(&text))
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return,
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, self.to_text()));
+
+        if let Err(err) = result {
+            warn!("Could not save campaign progress to {:?}: {}", path, err);
+        }
+    }
+}
+
+/// Start the game with `--campaign` to play the level packs instead of random boards
+pub struct Campaign {
+    pub active: bool,
+    pub levels: Vec<&'static str>,
+    pub index: usize,
+    pub progress: CampaignProgress,
+    handles: Vec<Handle<Level>>,
+}
+
+impl Default for Campaign {
+    fn default() -> Self {
+        let levels = LEVEL_PACKS
+            .iter()
+            .flat_map(|pack| pack.levels.iter().copied())
+            .collect();
+
+        Campaign {
+            active: std::env::args().any(|arg| arg == "--campaign"),
+            levels,
+            index: 0,
+            progress: CampaignProgress::default(),
+            handles: vec![],
+        }
+    }
+}
+
+impl Campaign {
+    fn is_completed(&self, index: usize) -> bool {
+        self.levels
+            .get(index)
+            .map_or(false, |level| self.progress.best.contains_key(*level))
+    }
+
+    /// The first level that hasn't been completed yet, or the last one once all are done
+    fn first_open_level(&self) -> usize {
+        (0..self.levels.len())
+            .find(|index| !self.is_completed(*index))
+            .unwrap_or_else(|| self.levels.len().saturating_sub(1))
+    }
+}
+
+fn start_campaign(
+    mut campaign: ResMut<Campaign>,
+    mut active_level: ResMut<ActiveLevel>,
+    asset_server: Res<AssetServer>,
+) {
+    if !campaign.active || campaign.levels.is_empty() {
+        return;
+    }
+
+    campaign.progress = CampaignProgress::load();
+    campaign.handles = campaign
+        .levels
+        .iter()
+        .map(|level| asset_server.load(*level))
+        .collect();
+    campaign.index = campaign.first_open_level();
+
+    if let Some(pack) = LEVEL_PACKS
+        .iter()
+        .find(|pack| pack.levels.contains(&campaign.levels[campaign.index]))
+    {
+        info!(
+            "Continuing the {} campaign at {}",
+            pack.name, campaign.levels[campaign.index]
+        );
+    }
+    active_level.handle = campaign.handles[campaign.index].clone();
+    active_level.random = false;
+}
+
+fn record_campaign_result(
+    mut ev_board_finished: EventReader<BoardFinishedEvent>,
+    mut campaign: ResMut<Campaign>,
+) {
+    for ev in ev_board_finished.iter() {
+        if !campaign.active || ev.result != MowResult::Perfect {
+            continue;
+        }
+
+        let level = campaign.levels[campaign.index];
+        let score = LevelScore {
+            stars: ev.stars,
+            command_count: ev.command_count,
+        };
+        if campaign.progress.record(level, score) {
+            campaign.progress.save();
+        }
+    }
+}
+
+/// Moves on to the next level once the current one is completed, otherwise retries it
+fn next_campaign_level(
+    mut ev_next_board: EventReader<NextBoardEvent>,
+    mut campaign: ResMut<Campaign>,
+    mut active_level: ResMut<ActiveLevel>,
+) {
+    for _ in ev_next_board.iter() {
+        if !campaign.active || campaign.handles.is_empty() {
+            continue;
+        }
+
+        if campaign.is_completed(campaign.index) && campaign.index + 1 < campaign.levels.len() {
+            campaign.index += 1;
+        }
+        active_level.handle = campaign.handles[campaign.index].clone();
+    }
+}
+
+#[test]
+fn progress_round_trip() {
+    let mut progress = CampaignProgress::default();
+    progress.record(
+        "levels/corner.level",
+        LevelScore {
+            stars: 2,
+            command_count: 9,
+        },
+    );
+    progress.record(
+        "levels/straight.level",
+        LevelScore {
+            stars: 3,
+            command_count: 6,
+        },
+    );
+
+    assert_eq!(
+        progress.to_text(),
+        "levels/corner.level 2 9\nlevels/straight.level 3 6\n"
+    );
+    assert_eq!(CampaignProgress::parse(&progress.to_text()), progress);
+}
+
+#[test]
+fn progress_keeps_best_score() {
+    let mut progress = CampaignProgress::default();
+    let score = |stars, command_count| LevelScore {
+        stars,
+        command_count,
+    };
+
+    assert!(progress.record("a.level", score(2, 9)));
+    assert!(!progress.record("a.level", score(1, 4)));
+    assert!(!progress.record("a.level", score(2, 10)));
+    assert!(progress.record("a.level", score(2, 8)));
+    assert_eq!(progress.best.get("a.level"), Some(&score(2, 8)));
+}
+
+#[test]
+fn campaign_continues_at_first_open_level() {
+    let mut campaign = Campaign {
+        active: true,
+        levels: vec!["a.level", "b.level", "c.level"],
+        ..default()
+    };
+    assert_eq!(campaign.first_open_level(), 0);
+
+    campaign.progress = CampaignProgress::parse("a.level 3 4\nb.level 1 20\n");
+    assert_eq!(campaign.first_open_level(), 2);
+
+    campaign.progress = CampaignProgress::parse("a.level 3 4\nb.level 1 20\nc.level 2 5\n");
+    assert_eq!(campaign.first_open_level(), 2);
+}
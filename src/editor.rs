@@ -0,0 +1,338 @@
+use crate::field::{canvas_square_translation, target_square_translation, Field, Obstacle};
+use crate::harvestor::{Harvestor, HarvestorCommands, HarvestorState, ProgramText};
+use crate::level::{ActiveLevel, Level};
+use crate::program::Program;
+use crate::puzzle::{is_in_bounds, Puzzle, HARVESTOR_START, HARVESTOR_START_FACING};
+use crate::ui::{
+    update_help_text, CommandsContainerMarker, FontHandle, HelpTextContainer,
+    ProcedureContainerMarker,
+};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use iyes_loopless::prelude::*;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LevelEditor>()
+            .add_system(open_editor)
+            .add_enter_system(HarvestorState::Editing, start_editing)
+            .add_exit_system(HarvestorState::Editing, stop_editing)
+            .add_system(edit_level.run_in_state(HarvestorState::Editing));
+    }
+}
+
+/// Where `S` saves the level that is being edited
+const EDITOR_LEVEL_PATH: &str = "assets/levels/custom.level";
+const EDITOR_MAX_SIZE: u32 = 20;
+/// How far from a square the cursor may be, in pixels, to still pick it
+const PICK_DISTANCE: f32 = 30.0;
+
+const EDITOR_HELP_TEXT: &str =
+    "Click a square to switch between wheat to harvest, standing wheat and obstacles, right click next to the field to place the harvestor\n\
+    F turns the harvestor, [ ] change the width, - = the height\n\
+    S saves, Enter plays the level, E goes back";
+
+/// Press `E` while entering commands to edit the current level.
+/// The level is edited as a copy so the original asset stays untouched.
+#[derive(Default)]
+pub struct LevelEditor {
+    handle: Handle<Level>,
+    /// The level that was played before opening the editor, restored when leaving with `E`
+    previous: Option<(Handle<Level>, bool)>,
+}
+
+fn open_editor(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    state: Res<CurrentState<HarvestorState>>,
+    program_text: Res<ProgramText>,
+) {
+    let can_edit = matches!(
+        state.0,
+        HarvestorState::AcceptingCommands | HarvestorState::Done
+    ) && program_text.typing.is_none();
+    if can_edit && keys.just_released(KeyCode::E) {
+        commands.insert_resource(NextState(HarvestorState::Editing));
+    }
+}
+
+/// The rows of the main commands and the procedures, emptied while editing
+type CommandRows = Or<(
+    With<CommandsContainerMarker>,
+    With<ProcedureContainerMarker>,
+)>;
+
+#[allow(clippy::too_many_arguments)]
+fn start_editing(
+    mut commands: Commands,
+    mut editor: ResMut<LevelEditor>,
+    mut active_level: ResMut<ActiveLevel>,
+    mut levels: ResMut<Assets<Level>>,
+    ui: Query<Entity, CommandRows>,
+    help_ui_container_q: Query<Entity, With<HelpTextContainer>>,
+    font: Res<FontHandle>,
+    fields_q: Query<Entity, With<Field>>,
+    harvestor_q: Query<Entity, With<Harvestor>>,
+) {
+    let mut level = levels
+        .get(&active_level.handle)
+        .cloned()
+        .unwrap_or_else(|| {
+            let puzzle = Puzzle {
+                size: UVec2::new(5, 5),
+                start: HARVESTOR_START,
+                facing: HARVESTOR_START_FACING,
+                ..default()
+            };
+            Level::new(puzzle, HashMap::new())
+        });
+    level
+        .metadata
+        .entry("name".to_string())
+        .or_insert_with(|| "Custom level".to_string());
+
+    // reopening after a playtest keeps the level from before the first opening
+    if editor.previous.is_none() {
+        editor.previous = Some((active_level.handle.clone(), active_level.random));
+    }
+    editor.handle = levels.add(level);
+    active_level.handle = editor.handle.clone();
+    active_level.random = false;
+
+    ui.iter()
+        .for_each(|e| commands.entity(e).despawn_descendants());
+    update_help_text(
+        &font,
+        &mut commands,
+        help_ui_container_q.single(),
+        EDITOR_HELP_TEXT,
+    );
+    respawn_board(&mut commands, &fields_q, &harvestor_q);
+}
+
+fn stop_editing(
+    mut commands: Commands,
+    help_ui_container_q: Query<Entity, With<HelpTextContainer>>,
+    font: Res<FontHandle>,
+) {
+    update_help_text(
+        &font,
+        &mut commands,
+        help_ui_container_q.single(),
+        "Press any arrow key to insert command.",
+    );
+}
+
+/// The field and harvestor spawn again from the edited level once they're gone
+fn respawn_board(
+    commands: &mut Commands,
+    fields_q: &Query<Entity, With<Field>>,
+    harvestor_q: &Query<Entity, With<Harvestor>>,
+) {
+    fields_q
+        .iter()
+        .chain(harvestor_q.iter())
+        .for_each(|e| commands.entity(e).despawn_recursive());
+}
+
+#[allow(clippy::too_many_arguments)]
+fn edit_level(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera_q: Query<(&Camera, &GlobalTransform)>,
+    mut editor: ResMut<LevelEditor>,
+    mut active_level: ResMut<ActiveLevel>,
+    mut levels: ResMut<Assets<Level>>,
+    fields_q: Query<Entity, With<Field>>,
+    harvestor_q: Query<Entity, With<Harvestor>>,
+) {
+    if keys.just_released(KeyCode::Return) {
+        // the board changed while editing
+        if let Some(level) = levels.get_mut(&editor.handle) {
+            level.par = level.puzzle.par();
+        }
+        commands.insert_resource(NextState(HarvestorState::AcceptingCommands));
+        return;
+    }
+    if keys.just_released(KeyCode::E) {
+        if let Some((handle, random)) = editor.previous.take() {
+            active_level.handle = handle;
+            active_level.random = random;
+        }
+        commands.insert_resource(NextState(HarvestorState::AcceptingCommands));
+        return;
+    }
+
+    let level = match levels.get_mut(&editor.handle) {
+        Some(level) => level,
+        None => return,
+    };
+    if keys.just_released(KeyCode::S) {
+        match std::fs::write(EDITOR_LEVEL_PATH, level.to_string()) {
+            Ok(()) => info!("Saved the level to {}", EDITOR_LEVEL_PATH),
+            Err(err) => warn!("Could not save the level to {}: {}", EDITOR_LEVEL_PATH, err),
+        }
+    }
+
+    let puzzle = &mut level.puzzle;
+    let mut size = puzzle.size;
+    if keys.just_released(KeyCode::LBracket) {
+        size.x -= 1;
+    } else if keys.just_released(KeyCode::RBracket) {
+        size.x += 1;
+    } else if keys.just_released(KeyCode::Minus) {
+        size.y -= 1;
+    } else if keys.just_released(KeyCode::Equals) {
+        size.y += 1;
+    }
+    let mut changed = resize(puzzle, size);
+
+    if keys.just_released(KeyCode::F) {
+        puzzle.facing = match puzzle.facing {
+            HarvestorCommands::Up => HarvestorCommands::Right,
+            HarvestorCommands::Right => HarvestorCommands::Down,
+            HarvestorCommands::Down => HarvestorCommands::Left,
+            HarvestorCommands::Left => HarvestorCommands::Up,
+        };
+        changed = true;
+    }
+
+    let cursor = windows
+        .get_primary()
+        .and_then(|window| window.cursor_position());
+    if let (Some(cursor), Ok((camera, camera_tf))) = (cursor, camera_q.get_single()) {
+        let size = puzzle.size;
+        let pick = |translation: fn(UVec2, IVec2) -> Vec3, positions: Vec<IVec2>| {
+            positions
+                .into_iter()
+                .filter_map(|position| {
+                    let screen =
+                        camera.world_to_viewport(camera_tf, translation(size, position))?;
+                    Some((position, screen.distance(cursor)))
+                })
+                .filter(|(_, distance)| *distance < PICK_DISTANCE)
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(position, _)| position)
+        };
+
+        if mouse_buttons.just_pressed(MouseButton::Left) {
+            let squares = squares_with_border(size)
+                .into_iter()
+                .filter(|position| is_in_bounds(*position, size))
+                .collect();
+            if let Some(position) = pick(target_square_translation, squares) {
+                cycle_square(puzzle, position);
+                changed = true;
+            }
+        }
+        if mouse_buttons.just_pressed(MouseButton::Right) {
+            let squares = squares_with_border(size)
+                .into_iter()
+                .filter(|position| !is_in_bounds(*position, size))
+                .collect();
+            if let Some(position) = pick(canvas_square_translation, squares) {
+                puzzle.start = position;
+                changed = true;
+            }
+        }
+    }
+
+    if changed {
+        // the solution that came with the level doesn't fit the edited one anymore
+        puzzle.solution = Program::default();
+        respawn_board(&mut commands, &fields_q, &harvestor_q);
+    }
+}
+
+/// Every square of the field plus the ring around it where the harvestor can start
+fn squares_with_border(size: UVec2) -> Vec<IVec2> {
+    (-1..=size.x as i32)
+        .flat_map(|x| (-1..=size.y as i32).map(move |y| IVec2::new(x, y)))
+        .collect()
+}
+
+/// Goes from wheat to harvest to standing wheat, then through every obstacle and back
+fn cycle_square(puzzle: &mut Puzzle, position: IVec2) {
+    let coord = (position.x, position.y);
+    let standing = *puzzle.target.get(&coord).unwrap_or(&false);
+
+    let (standing, obstacle) = match (standing, puzzle.obstacles.get(&coord)) {
+        (false, _) => (true, None),
+        (true, None) => (true, Some(Obstacle::Rock)),
+        (true, Some(Obstacle::Rock)) => (true, Some(Obstacle::Tree)),
+        (true, Some(Obstacle::Tree)) => (true, Some(Obstacle::Barn)),
+        (true, Some(Obstacle::Barn)) => (false, None),
+    };
+    puzzle.target.insert(coord, standing);
+    match obstacle {
+        Some(obstacle) => puzzle.obstacles.insert(coord, obstacle),
+        None => puzzle.obstacles.remove(&coord),
+    };
+}
+
+/// Changes the field size, new squares are left standing.
+/// Moves the harvestor back to the default start when it ends up away from the field.
+fn resize(puzzle: &mut Puzzle, size: UVec2) -> bool {
+    let size = size.clamp(UVec2::ONE, UVec2::splat(EDITOR_MAX_SIZE));
+    if size == puzzle.size {
+        return false;
+    }
+
+    puzzle
+        .target
+        .retain(|(x, y), _| is_in_bounds(IVec2::new(*x, *y), size));
+    puzzle
+        .obstacles
+        .retain(|(x, y), _| is_in_bounds(IVec2::new(*x, *y), size));
+    for position in squares_with_border(size) {
+        if is_in_bounds(position, size) && !is_in_bounds(position, puzzle.size) {
+            puzzle
+                .target
+                .entry((position.x, position.y))
+                .or_insert(true);
+        }
+    }
+    puzzle.size = size;
+
+    let start = puzzle.start;
+    let next_to_field = start.cmpge(IVec2::NEG_ONE).all()
+        && start.cmple(size.as_ivec2()).all()
+        && !is_in_bounds(start, size);
+    if !next_to_field {
+        puzzle.start = HARVESTOR_START;
+        puzzle.facing = HARVESTOR_START_FACING;
+    }
+
+    true
+}
+
+#[test]
+fn resize_keeps_squares_inside_the_field() {
+    let mut puzzle = Puzzle {
+        size: UVec2::new(2, 2),
+        start: IVec2::new(1, 2),
+        facing: HarvestorCommands::Left,
+        ..default()
+    };
+    cycle_square(&mut puzzle, IVec2::new(1, 1));
+    assert_eq!(puzzle.target.get(&(1, 1)), Some(&true));
+    cycle_square(&mut puzzle, IVec2::new(1, 1));
+    assert_eq!(puzzle.obstacles.get(&(1, 1)), Some(&Obstacle::Rock));
+
+    assert!(resize(&mut puzzle, UVec2::new(3, 2)));
+    assert_eq!(puzzle.target.get(&(2, 0)), Some(&true));
+    assert_eq!(puzzle.target.get(&(0, 0)), None);
+    assert_eq!(puzzle.start, IVec2::new(1, 2));
+
+    assert!(resize(&mut puzzle, UVec2::new(1, 1)));
+    assert_eq!(puzzle.target.get(&(1, 1)), None);
+    assert_eq!(puzzle.obstacles.get(&(1, 1)), None);
+    assert_eq!(puzzle.start, HARVESTOR_START);
+
+    assert!(!resize(&mut puzzle, UVec2::new(0, 1)));
+    assert_eq!(puzzle.size, UVec2::new(1, 1));
+}
@@ -1,14 +1,23 @@
+#[cfg(test)]
+use crate::harvestor::HarvestorCommands::{self, *};
 use crate::harvestor::{
-    command_to_direction, watch_havestor_finished_moves, Harvestor, HarvestorCommands,
-    HarvestorCommandsClearedEvent, HarvestorState,
+    watch_havestor_finished_moves, CommandCount, Harvestor, HarvestorCommandsClearedEvent,
+    HarvestorMovedEvent, HarvestorState, HintCount, TimeSpentWaitingOnCommands, Timeline,
 };
+use crate::level::{ActiveLevel, Level};
+#[cfg(test)]
+use crate::simulation::simulate;
+use crate::simulation::{Crash, CrashReason, FieldSpec};
 use crate::ui::{update_help_text, FontHandle, HelpTextContainer};
 use bevy::prelude::*;
 use bevy::utils::HashMap;
 use bevy_inspector_egui::Inspectable;
-use itertools::Itertools;
-use iyes_loopless::prelude::AppLooplessStateExt;
-use rand::Rng;
+use iyes_loopless::prelude::*;
+use rand::{
+    distributions::{Distribution, Standard},
+    Rng,
+};
+use std::time::Duration;
 pub struct FieldPlugin;
 
 impl Plugin for FieldPlugin {
@@ -17,8 +26,17 @@ impl Plugin for FieldPlugin {
             .add_system(mow_target_field.after(watch_havestor_finished_moves))
             .init_resource::<FieldMaterialResource>()
             .add_system(change_mowed_material)
+            .add_event::<BoardFinishedEvent>()
             .add_system(compare_fields_on_commands_cleared.after(mow_target_field))
-            .add_enter_system(HarvestorState::AcceptingCommands, setup);
+            .add_system(show_field_diff)
+            .add_system(show_harvestor_position)
+            .add_system(show_rewound_field.run_in_state(HarvestorState::Done))
+            .add_enter_system(HarvestorState::AcceptingCommands, despawn_fields)
+            .add_enter_system(
+                HarvestorState::AcceptingCommands,
+                clear_field_diff.before(despawn_fields),
+            )
+            .add_system(setup.run_not_in_state(HarvestorState::Running));
         // .register_inspectable::<Field>();
     }
 }
@@ -27,6 +45,13 @@ impl Plugin for FieldPlugin {
 struct FieldMaterialResource {
     mowed: Handle<StandardMaterial>,
     not_mowed: Handle<StandardMaterial>,
+    diff_correct: Handle<StandardMaterial>,
+    diff_wrong: Handle<StandardMaterial>,
+    diff_missed: Handle<StandardMaterial>,
+    rock: Handle<StandardMaterial>,
+    tree: Handle<StandardMaterial>,
+    barn: Handle<StandardMaterial>,
+    position: Handle<StandardMaterial>,
 }
 #[derive(Inspectable, PartialEq, Default, Debug)]
 enum FieldType {
@@ -41,80 +66,107 @@ pub struct Field {
     field_type: FieldType,
     #[inspectable(ignore)]
     mowed: HashMap<(i32, i32), bool>,
+    #[inspectable(ignore)]
+    obstacles: HashMap<(i32, i32), Obstacle>,
+}
+
+impl Field {
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    /// The field for a harvestor with a header one square wide
+    pub fn spec(&self) -> FieldSpec {
+        FieldSpec {
+            size: self.size,
+            obstacles: self.obstacles.clone(),
+            ..default()
+        }
+    }
+}
+
+/// Something standing in the field that the harvestor can't drive through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Obstacle {
+    Rock,
+    Tree,
+    Barn,
+}
+
+impl Distribution<Obstacle> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Obstacle {
+        match rng.gen_range(0..=2) {
+            0 => Obstacle::Rock,
+            1 => Obstacle::Tree,
+            _ => Obstacle::Barn,
+        }
+    }
 }
 
+fn despawn_fields(mut commands: Commands, fields_q: Query<Entity, With<Field>>) {
+    fields_q.iter().for_each(|e| {
+        commands.entity(e).despawn_recursive();
+    });
+}
+
+/// Spawns the fields of the active level as soon as it's loaded, or again after the editor
+/// despawned them
 fn setup(
     mut commands: Commands,
     mut field_material: ResMut<FieldMaterialResource>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    fields_q: Query<Entity, With<Field>>,
+    fields_q: Query<(), With<Field>>,
+    active_level: Res<ActiveLevel>,
+    levels: Res<Assets<Level>>,
 ) {
-    fields_q.iter().for_each(|e| {
-        commands.entity(e).despawn_recursive();
-    });
+    if !fields_q.is_empty() {
+        return;
+    }
+    let puzzle = match levels.get(&active_level.handle) {
+        Some(level) => &level.puzzle,
+        None => return,
+    };
 
     let material_field_fresh = materials.add(FIELD_FRESH_COLOR.into());
     let material_field_mowed = materials.add(FIELD_MOWED_COLOR.into());
     field_material.not_mowed = material_field_fresh;
     field_material.mowed = material_field_mowed;
+    field_material.diff_correct = materials.add(FIELD_DIFF_CORRECT_COLOR.into());
+    field_material.diff_wrong = materials.add(FIELD_DIFF_WRONG_COLOR.into());
+    field_material.diff_missed = materials.add(FIELD_DIFF_MISSED_COLOR.into());
+    field_material.rock = materials.add(OBSTACLE_ROCK_COLOR.into());
+    field_material.tree = materials.add(OBSTACLE_TREE_COLOR.into());
+    field_material.barn = materials.add(OBSTACLE_BARN_COLOR.into());
+    field_material.position = materials.add(StandardMaterial {
+        base_color: HARVESTOR_POSITION_COLOR,
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    });
 
-    let mut target_mowed = HashMap::new();
-
-    mow_random_path_in_field(
-        IVec2::new(0, 0),
-        25,
-        25,
-        UVec2::new(10, 10),
-        &mut target_mowed,
-    );
     commands.spawn().insert(Field {
-        size: UVec2::new(10, 10),
+        size: puzzle.size,
         field_type: FieldType::Target,
-        mowed: target_mowed,
+        mowed: puzzle.target.clone(),
+        obstacles: puzzle.obstacles.clone(),
     });
     commands.spawn().insert(Field {
-        size: UVec2::new(10, 10),
+        size: puzzle.size,
         field_type: FieldType::Canvas,
         mowed: HashMap::new(),
+        obstacles: puzzle.obstacles.clone(),
     });
 }
 
-fn mow_random_path_in_field(
-    start: IVec2,
-    amount: u32,
-    chance_of_redirect: u32,
-    field_size: UVec2,
-    field: &mut HashMap<(i32, i32), bool>,
-) {
-    (0..field_size.x)
-        .cartesian_product(0..field_size.y)
-        .for_each(|(x, y)| {
-            field.insert((x as i32, y as i32), true);
-        });
-    let mut start = start;
-    let mut random_direction = HarvestorCommands::Up;
-
-    let mut rng = rand::thread_rng();
-
-    for _ in 0..=amount {
-        field.insert((start.x, start.y), false);
-        let num = rng.gen_range(0..100);
-        if num < chance_of_redirect {
-            random_direction = rand::random();
-        }
-        let vec_direction = command_to_direction(&random_direction);
-
-        start.x -= vec_direction.x as i32;
-        start.y += vec_direction.z as i32;
-        let _start = start.clamp(
-            IVec2::new(0, 0),
-            IVec2::new(field_size.x as i32, field_size.y as i32),
-        );
-    }
-}
-
 const FIELD_FRESH_COLOR: Color = Color::rgb(0.536, 0.389, 0.076);
 const FIELD_MOWED_COLOR: Color = Color::rgb(0.4, 0.2, 0.0);
+const FIELD_DIFF_CORRECT_COLOR: Color = Color::rgb(0.2, 0.6, 0.1);
+const FIELD_DIFF_WRONG_COLOR: Color = Color::rgb(0.8, 0.1, 0.1);
+const FIELD_DIFF_MISSED_COLOR: Color = Color::rgb(0.9, 0.8, 0.1);
+const OBSTACLE_ROCK_COLOR: Color = Color::rgb(0.5, 0.5, 0.5);
+const OBSTACLE_TREE_COLOR: Color = Color::rgb(0.1, 0.4, 0.1);
+const OBSTACLE_BARN_COLOR: Color = Color::rgb(0.6, 0.1, 0.1);
+const HARVESTOR_POSITION_COLOR: Color = Color::rgba(0.3, 0.9, 0.4, 0.5);
 
 pub const FIELD_SIZE: f32 = 0.2;
 pub const FIELD_MARGIN_SIZE: f32 = 0.01;
@@ -123,6 +175,30 @@ pub const FIELD_THICKNESS: f32 = 0.02;
 #[derive(Component)]
 pub struct FieldSquareMarker(UVec2);
 
+/// The canvas and target field are placed next to each other, a field's width apart
+fn square_translation(field_type: &FieldType, size: UVec2, position: IVec2) -> Vec3 {
+    let width = size.x as f32 * (FIELD_SIZE + FIELD_MARGIN_SIZE);
+    let field_type_offset = match field_type {
+        FieldType::Target => Vec3::X * width * 1.5,
+        FieldType::Canvas => -Vec3::X * width * 0.5,
+    };
+
+    let world_x = -1.0 * position.x as f32 * (FIELD_SIZE + FIELD_MARGIN_SIZE);
+    let world_y = position.y as f32 * (FIELD_SIZE + FIELD_MARGIN_SIZE);
+
+    Vec3::new(world_x + field_type_offset.x, 0.0, world_y)
+}
+
+/// Where a square of the canvas field, the one the harvestor drives over, is in the world
+pub fn canvas_square_translation(size: UVec2, position: IVec2) -> Vec3 {
+    square_translation(&FieldType::Canvas, size, position)
+}
+
+/// Where a square of the target field, the one showing what to harvest, is in the world
+pub fn target_square_translation(size: UVec2, position: IVec2) -> Vec3 {
+    square_translation(&FieldType::Target, size, position)
+}
+
 fn render_fields(
     query: Query<(Entity, &Field), Added<Field>>,
     mut commands: Commands,
@@ -131,19 +207,32 @@ fn render_fields(
 ) {
     let mesh = Mesh::from(shape::Cube { size: FIELD_SIZE });
     let handle = meshes.add(mesh);
+    let rock_mesh = meshes.add(Mesh::from(shape::Icosphere {
+        radius: FIELD_SIZE * 0.35,
+        subdivisions: 1,
+    }));
+    let tree_mesh = meshes.add(Mesh::from(shape::Capsule {
+        radius: FIELD_SIZE * 0.2,
+        depth: FIELD_SIZE * 0.6,
+        ..default()
+    }));
+    let barn_mesh = meshes.add(Mesh::from(shape::Box::new(
+        FIELD_SIZE * 0.9,
+        FIELD_SIZE * 0.6,
+        FIELD_SIZE * 0.9,
+    )));
+    let position_mesh = meshes.add(Mesh::from(shape::Box::new(
+        FIELD_SIZE,
+        FIELD_SIZE * FIELD_THICKNESS,
+        FIELD_SIZE,
+    )));
 
     query.iter().for_each(|(e, field)| {
-        let pos = match field.field_type {
-            FieldType::Target => Vec3::X,
-            FieldType::Canvas => -Vec3::X,
-        };
-        let field_type_offset = pos * 2.0 + Vec3::X;
-
         let mut entity = commands.entity(e);
         entity.insert_bundle(SpatialBundle { ..default() });
 
-        (0..field.size.y)
-            .flat_map(|x| (0..field.size.x).map(move |y| (x, y)))
+        (0..field.size.x)
+            .flat_map(|x| (0..field.size.y).map(move |y| (x, y)))
             .for_each(|(x, y)| {
                 let material = if *field.mowed.get(&(x as i32, y as i32)).unwrap_or(&false) {
                     field_material.mowed.clone()
@@ -151,34 +240,84 @@ fn render_fields(
                     field_material.not_mowed.clone()
                 };
 
-                let world_x = -1.0 * x as f32 * (FIELD_SIZE + FIELD_MARGIN_SIZE);
-                let world_y = y as f32 * (FIELD_SIZE + FIELD_MARGIN_SIZE);
+                let translation = square_translation(
+                    &field.field_type,
+                    field.size,
+                    IVec2::new(x as i32, y as i32),
+                );
                 entity.with_children(|cb| {
                     cb.spawn()
                         .insert_bundle(PbrBundle {
                             mesh: handle.clone(),
                             material,
-                            transform: Transform::from_xyz(
-                                world_x + field_type_offset.x,
-                                0.0,
-                                world_y,
-                            )
-                            .with_scale(Vec3::new(
-                                1.0,
-                                FIELD_THICKNESS,
-                                1.0,
-                            )),
+                            transform: Transform::from_translation(translation)
+                                .with_scale(Vec3::new(1.0, FIELD_THICKNESS, 1.0)),
                             ..default()
                         })
                         .insert(FieldSquareMarker(UVec2::new(x, y)));
                 });
             });
+
+        field.obstacles.iter().for_each(|((x, y), obstacle)| {
+            let (mesh, material, height) = match obstacle {
+                Obstacle::Rock => (rock_mesh.clone(), field_material.rock.clone(), 0.2),
+                Obstacle::Tree => (tree_mesh.clone(), field_material.tree.clone(), 0.5),
+                Obstacle::Barn => (barn_mesh.clone(), field_material.barn.clone(), 0.3),
+            };
+            let translation = square_translation(&field.field_type, field.size, IVec2::new(*x, *y))
+                + Vec3::Y * FIELD_SIZE * height;
+
+            entity.with_children(|cb| {
+                cb.spawn_bundle(PbrBundle {
+                    mesh,
+                    material,
+                    transform: Transform::from_translation(translation),
+                    ..default()
+                });
+            });
+        });
+
+        if field.field_type == FieldType::Canvas {
+            entity.with_children(|cb| {
+                cb.spawn_bundle(PbrBundle {
+                    mesh: position_mesh.clone(),
+                    material: field_material.position.clone(),
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                })
+                .insert(HarvestorPositionMarker);
+            });
+        }
+    });
+}
+
+/// Shows the square the harvestor is on as far as the run is concerned, which is where it
+/// stops when the run is paused
+#[derive(Component)]
+struct HarvestorPositionMarker;
+
+fn show_harvestor_position(
+    harvestor_q: Query<&Harvestor>,
+    field_q: Query<&Field>,
+    mut marker_q: Query<(&mut Transform, &mut Visibility), With<HarvestorPositionMarker>>,
+    state: Res<CurrentState<HarvestorState>>,
+) {
+    let field = field_q.iter().find(|f| f.field_type == FieldType::Canvas);
+    let (h, field) = match (harvestor_q.iter().next(), field) {
+        (Some(h), Some(field)) => (h, field),
+        _ => return,
+    };
+
+    marker_q.iter_mut().for_each(|(mut tf, mut visibility)| {
+        visibility.is_visible = state.0 == HarvestorState::Running;
+        tf.translation = canvas_square_translation(field.size, h.position)
+            + Vec3::Y * FIELD_SIZE * FIELD_THICKNESS;
     });
 }
 
 // this ok?
 fn change_mowed_material(
-    field_q: Query<(&Field, &Children)>,
+    field_q: Query<(&Field, &Children), Without<FieldDiffOverlay>>,
     field_square_q: Query<&FieldSquareMarker>,
     mut commands: Commands,
     field_material: Res<FieldMaterialResource>,
@@ -204,78 +343,380 @@ fn change_mowed_material(
     });
 }
 
+/// Marks the canvas field to show how every square compares to the target after a run
+#[derive(Component)]
+struct FieldDiffOverlay;
+
+fn show_field_diff(
+    overlay_q: Query<(&Field, &Children), Added<FieldDiffOverlay>>,
+    field_q: Query<&Field>,
+    field_square_q: Query<&FieldSquareMarker>,
+    mut commands: Commands,
+    field_material: Res<FieldMaterialResource>,
+) {
+    let target = match field_q.iter().find(|f| f.field_type == FieldType::Target) {
+        Some(target) => target,
+        None => return,
+    };
+
+    overlay_q.iter().for_each(|(canvas, children)| {
+        children.iter().for_each(|field_square_entity| {
+            if let Ok(fs) = field_square_q.get(*field_square_entity) {
+                let coord = (fs.0.x as i32, fs.0.y as i32);
+                let material = match SquareOutcome::new(target, canvas, coord) {
+                    SquareOutcome::Correct => field_material.diff_correct.clone(),
+                    SquareOutcome::Wrong => field_material.diff_wrong.clone(),
+                    SquareOutcome::Missed => field_material.diff_missed.clone(),
+                    SquareOutcome::Untouched => return,
+                };
+
+                commands.entity(*field_square_entity).insert(material);
+            }
+        });
+    });
+}
+
+fn clear_field_diff(
+    overlay_q: Query<(Entity, &Field, &Children), With<FieldDiffOverlay>>,
+    field_square_q: Query<&FieldSquareMarker>,
+    mut commands: Commands,
+    field_material: Res<FieldMaterialResource>,
+) {
+    overlay_q.iter().for_each(|(e, field, children)| {
+        children.iter().for_each(|field_square_entity| {
+            if let Ok(fs) = field_square_q.get(*field_square_entity) {
+                let is_mowed = field
+                    .mowed
+                    .get(&(fs.0.x as i32, fs.0.y as i32))
+                    .unwrap_or(&false);
+                let material = if *is_mowed {
+                    field_material.mowed.clone()
+                } else {
+                    field_material.not_mowed.clone()
+                };
+
+                commands.entity(*field_square_entity).insert(material);
+            }
+        });
+        commands.entity(e).remove::<FieldDiffOverlay>();
+    });
+}
+
+/// Shows the canvas field as it was at the snapshot a finished run is rewound to, back at the
+/// end of the run it's compared to the target again
+fn show_rewound_field(
+    timeline: Res<Timeline>,
+    mut field_q: Query<(Entity, &mut Field, &Children, Option<&FieldDiffOverlay>)>,
+    field_square_q: Query<&FieldSquareMarker>,
+    mut commands: Commands,
+    field_material: Res<FieldMaterialResource>,
+) {
+    if !timeline.is_changed() {
+        return;
+    }
+    let (shown, snapshot) = match timeline
+        .shown
+        .and_then(|shown| Some((shown, timeline.snapshots.get(shown)?)))
+    {
+        Some(shown) => shown,
+        None => return,
+    };
+    let at_end = shown + 1 == timeline.snapshots.len();
+
+    field_q
+        .iter_mut()
+        .for_each(|(e, mut field, children, overlay)| {
+            if field.field_type != FieldType::Canvas {
+                return;
+            }
+            field.mowed = snapshot.mowed.clone();
+            if at_end {
+                if overlay.is_none() {
+                    commands.entity(e).insert(FieldDiffOverlay);
+                }
+                return;
+            }
+            children.iter().for_each(|field_square_entity| {
+                if let Ok(fs) = field_square_q.get(*field_square_entity) {
+                    let coord = (fs.0.x as i32, fs.0.y as i32);
+                    let material = if snapshot.mowed.contains_key(&coord) {
+                        field_material.mowed.clone()
+                    } else {
+                        field_material.not_mowed.clone()
+                    };
+
+                    commands.entity(*field_square_entity).insert(material);
+                }
+            });
+            commands.entity(e).remove::<FieldDiffOverlay>();
+        });
+}
+
+/// Harvests the squares the harvestor arrives on, starting with the one it's placed on
 fn mow_target_field(
-    harvestor_q: Query<&Harvestor, Changed<Harvestor>>,
+    mut ev_moved: EventReader<HarvestorMovedEvent>,
+    spawned_q: Query<&Harvestor, Added<Harvestor>>,
     mut field_q: Query<&mut Field>,
 ) {
-    harvestor_q.iter().for_each(|h| {
+    let spawned = spawned_q
+        .iter()
+        .map(|h| (h.position, h.direction.clone(), h.header_width));
+    // squares driven onto with the header raised are left standing
+    let moved = ev_moved
+        .iter()
+        .filter(|moved| !moved.header_raised)
+        .map(|moved| (moved.position, moved.facing.clone(), moved.header_width));
+    for (position, facing, header_width) in spawned.chain(moved) {
         field_q.iter_mut().for_each(|mut field| {
             if field.field_type != FieldType::Canvas {
                 return;
             }
-            field.mowed.insert((h.position.x, h.position.y), true);
+            // a wide header also harvests the squares next to the harvestor, as far as they're
+            // on the field and not under an obstacle
+            let spec = FieldSpec {
+                header_width,
+                ..field.spec()
+            };
+            for square in spec.swath(position, &facing) {
+                field.mowed.insert((square.x, square.y), true);
+            }
         });
-    });
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn compare_fields_on_commands_cleared(
     mut ev_harvestor_commands_cleared: EventReader<HarvestorCommandsClearedEvent>,
-    field_q: Query<&Field>,
+    field_q: Query<(Entity, &Field)>,
+    harvestor_q: Query<&Harvestor>,
     mut commands: Commands,
     font: Res<FontHandle>,
     help_ui_container_q: Query<Entity, With<HelpTextContainer>>,
+    active_level: Res<ActiveLevel>,
+    levels: Res<Assets<Level>>,
+    command_count: Res<CommandCount>,
+    time_waiting: Res<TimeSpentWaitingOnCommands>,
+    hint_count: Res<HintCount>,
+    mut ev_board_finished: EventWriter<BoardFinishedEvent>,
 ) {
     for _ in ev_harvestor_commands_cleared.iter() {
-        let target_field = field_q.iter().find(|f| f.field_type == FieldType::Target);
-        let canvas_field = field_q.iter().find(|f| f.field_type == FieldType::Canvas);
-
-        if let (Some(target), Some(canvas)) = (target_field, canvas_field) {
-            let result = compare_fields(target, canvas);
-
-            let result_text = match result {
-                MowResult::Perfect => "Success! :) Press space to play again",
-                MowResult::TooMuch => "Too many fields are harvested :( Press space to play again",
-                MowResult::TooLittle => {
-                    "Some fields are not harvested :( Press space to play again"
-                }
+        let target_field = field_q
+            .iter()
+            .find(|(_, f)| f.field_type == FieldType::Target);
+        let canvas_field = field_q
+            .iter()
+            .find(|(_, f)| f.field_type == FieldType::Canvas);
+
+        let level = levels.get(&active_level.handle);
+
+        if let (Some((_, target)), Some((canvas_entity, canvas)), Some(level)) =
+            (target_field, canvas_field, level)
+        {
+            commands.entity(canvas_entity).insert(FieldDiffOverlay);
+
+            let crash = harvestor_q.iter().find_map(|h| h.crash);
+            let report = MowReport::new(
+                target,
+                canvas,
+                command_count.0,
+                level.par,
+                time_waiting.elapsed(),
+                hint_count.used,
+                crash,
+            );
+
+            let result_text = match report.result {
+                MowResult::Perfect => "Success! :)".to_string(),
+                MowResult::TooMuch => "Too many fields are harvested :(".to_string(),
+                MowResult::TooLittle => "Some fields are not harvested :(".to_string(),
+                MowResult::Crashed(Crash {
+                    reason: CrashReason::OutOfBounds,
+                    command_index,
+                }) => format!(
+                    "The harvestor drove off the field at command {} :(",
+                    command_index + 1
+                ),
+                MowResult::Crashed(Crash {
+                    reason: CrashReason::Obstacle,
+                    command_index,
+                }) => format!(
+                    "The harvestor ran into an obstacle at command {} :(",
+                    command_index + 1
+                ),
             };
+            let report_text = format!(
+                "{} Stars: {}/3\n\
+                {} harvested, {} too many, {} missed ({:.0}% accuracy)\n\
+                {} commands (par {}) in {:.1}s, {} hints. Press space to continue or left to rewind",
+                result_text,
+                report.stars,
+                report.correct,
+                report.wrong,
+                report.missed,
+                report.accuracy,
+                command_count.0,
+                level.par,
+                time_waiting.elapsed().as_secs_f32(),
+                hint_count.used,
+            );
 
             let e = help_ui_container_q.single();
-            update_help_text(&font, &mut commands, e, result_text);
+            update_help_text(&font, &mut commands, e, &report_text);
+
+            ev_board_finished.send(BoardFinishedEvent {
+                result: report.result,
+                stars: report.stars,
+                command_count: command_count.0,
+            });
         }
     }
 }
 
+pub struct BoardFinishedEvent {
+    pub result: MowResult,
+    pub stars: u8,
+    pub command_count: usize,
+}
+
 #[derive(PartialEq, Debug)]
-enum MowResult {
+pub enum MowResult {
     Perfect,
     TooMuch,
     TooLittle,
+    /// The harvestor drove off the field or into an obstacle and stopped there
+    Crashed(Crash),
 }
 
-fn compare_fields(field_target: &Field, field_canvas: &Field) -> MowResult {
-    let _result = false;
-    // canvas should not have mowed a field square that's mowed in target
-    for (coord, canvas_mowed) in field_canvas.mowed.iter() {
-        let target_mowed = field_target.mowed.get(coord).unwrap_or(&false);
+enum SquareOutcome {
+    /// Harvested and had to be harvested
+    Correct,
+    /// Harvested but should have been left standing
+    Wrong,
+    /// Had to be harvested but wasn't
+    Missed,
+    /// Left standing as it should
+    Untouched,
+}
 
-        if *canvas_mowed && *target_mowed {
-            return MowResult::TooMuch;
+impl SquareOutcome {
+    fn new(field_target: &Field, field_canvas: &Field, coord: (i32, i32)) -> Self {
+        let target_mowed = field_target.mowed.get(&coord).unwrap_or(&false);
+        let canvas_mowed = field_canvas.mowed.get(&coord).unwrap_or(&false);
+
+        match (target_mowed, canvas_mowed) {
+            (false, true) => SquareOutcome::Correct,
+            (true, true) => SquareOutcome::Wrong,
+            (false, false) => SquareOutcome::Missed,
+            (true, false) => SquareOutcome::Untouched,
         }
     }
+}
 
-    // all target unmowed squared should be mowed in canvas
-    for coord in (0..field_target.size.y)
-        .flat_map(|x| (0..field_target.size.x).map(move |y| (x as i32, y as i32)))
-    {
-        let mowed = field_target.mowed.get(&coord).unwrap_or(&false);
-        let field_is_mowed = field_canvas.mowed.get(&coord).unwrap_or(&false);
-        if !mowed && !field_is_mowed {
-            return MowResult::TooLittle;
+/// Seconds a player may take per command of the par solution and still earn the time star
+const PAR_SECONDS_PER_COMMAND: f32 = 2.0;
+
+#[derive(PartialEq, Debug)]
+struct MowReport {
+    result: MowResult,
+    /// Squares that had to be harvested and were
+    correct: usize,
+    /// Squares that were harvested but should have been left standing
+    wrong: usize,
+    /// Squares that had to be harvested but weren't
+    missed: usize,
+    /// Percentage of the squares that were or should have been harvested that ended up as they
+    /// should, squares that rightly stayed standing don't count
+    accuracy: f32,
+    stars: u8,
+}
+
+impl MowReport {
+    fn new(
+        field_target: &Field,
+        field_canvas: &Field,
+        command_count: usize,
+        par: usize,
+        time_spent: Duration,
+        hints: usize,
+        crash: Option<Crash>,
+    ) -> Self {
+        let mut correct = 0;
+        let mut wrong = 0;
+        let mut missed = 0;
+
+        for coord in (0..field_target.size.x)
+            .flat_map(|x| (0..field_target.size.y).map(move |y| (x as i32, y as i32)))
+        {
+            match SquareOutcome::new(field_target, field_canvas, coord) {
+                SquareOutcome::Correct => correct += 1,
+                SquareOutcome::Wrong => wrong += 1,
+                SquareOutcome::Missed => missed += 1,
+                SquareOutcome::Untouched => {}
+            }
+        }
+
+        let result = if let Some(crash) = crash {
+            MowResult::Crashed(crash)
+        } else if wrong > 0 {
+            MowResult::TooMuch
+        } else if missed > 0 {
+            MowResult::TooLittle
+        } else {
+            MowResult::Perfect
+        };
+
+        let judged = correct + wrong + missed;
+        let accuracy = if judged == 0 {
+            100.0
+        } else {
+            correct as f32 / judged as f32 * 100.0
+        };
+
+        let stars = if result == MowResult::Perfect {
+            let within_par = command_count <= par;
+            let within_time = time_spent.as_secs_f32() <= par as f32 * PAR_SECONDS_PER_COMMAND;
+
+            // every hint costs a star, but a perfect harvest always earns one
+            (1 + within_par as usize + within_time as usize)
+                .saturating_sub(hints)
+                .max(1) as u8
+        } else {
+            0
+        };
+
+        MowReport {
+            result,
+            correct,
+            wrong,
+            missed,
+            accuracy,
+            stars,
         }
     }
+}
 
-    MowResult::Perfect
+#[cfg(test)]
+fn compare_fields(field_target: &Field, field_canvas: &Field) -> MowResult {
+    MowReport::new(field_target, field_canvas, 0, 0, Duration::ZERO, 0, None).result
+}
+
+/// The canvas after the harvestor ran `commands` on the target field
+#[cfg(test)]
+fn simulated_canvas(
+    field_target: &Field,
+    start: IVec2,
+    facing: HarvestorCommands,
+    commands: &[HarvestorCommands],
+) -> (Field, Option<Crash>) {
+    let trace = simulate(start, facing, commands, &field_target.spec());
+    let field_canvas = Field {
+        size: field_target.size,
+        field_type: FieldType::Canvas,
+        mowed: trace.mowed,
+        obstacles: field_target.obstacles.clone(),
+    };
+
+    (field_canvas, trace.crash)
 }
 
 #[test]
@@ -291,11 +732,7 @@ fn fully_mowed_field() {
         mowed: target_mowed,
         ..default()
     };
-    let field_canvas = Field {
-        size: UVec2::new(2, 2),
-        mowed: HashMap::new(),
-        ..default()
-    };
+    let (field_canvas, _) = simulated_canvas(&field_target, IVec2::new(0, -1), Left, &[]);
 
     assert_eq!(
         compare_fields(&field_target, &field_canvas),
@@ -311,17 +748,12 @@ fn fully_unmowed_field() {
         mowed: target_mowed,
         ..default()
     };
-    let mut canvas_mowed = HashMap::new();
-    canvas_mowed.insert((0, 0), true);
-    canvas_mowed.insert((0, 1), true);
-    canvas_mowed.insert((1, 0), true);
-    canvas_mowed.insert((1, 1), true);
-
-    let field_canvas = Field {
-        size: UVec2::new(2, 2),
-        mowed: canvas_mowed,
-        ..default()
-    };
+    let (field_canvas, _) = simulated_canvas(
+        &field_target,
+        IVec2::new(0, -1),
+        Left,
+        &[Up, Up, Right, Down],
+    );
 
     assert_eq!(
         compare_fields(&field_target, &field_canvas),
@@ -341,15 +773,7 @@ fn partial_mowed() {
         mowed: target_mowed,
         ..default()
     };
-    let mut canvas_mowed = HashMap::new();
-    canvas_mowed.insert((1, 0), true);
-    canvas_mowed.insert((1, 1), true);
-
-    let field_canvas = Field {
-        size: UVec2::new(2, 2),
-        mowed: canvas_mowed,
-        ..default()
-    };
+    let (field_canvas, _) = simulated_canvas(&field_target, IVec2::new(1, -1), Up, &[Up, Up]);
 
     assert_eq!(
         compare_fields(&field_target, &field_canvas),
@@ -367,23 +791,35 @@ fn too_little_mowed() {
         mowed: target_mowed,
         ..default()
     };
-    let mut canvas_mowed = HashMap::new();
-    canvas_mowed.insert((1, 1), true);
+    let (field_canvas, _) = simulated_canvas(&field_target, IVec2::new(1, 2), Down, &[Down]);
 
-    let field_canvas = Field {
+    assert_eq!(
+        compare_fields(&field_target, &field_canvas),
+        MowResult::TooLittle
+    );
+}
+
+#[test]
+fn too_much_mowed() {
+    let mut target_mowed = HashMap::new();
+    target_mowed.insert((0, 0), true);
+    target_mowed.insert((0, 1), true);
+    let field_target = Field {
         size: UVec2::new(2, 2),
-        mowed: canvas_mowed,
+        mowed: target_mowed,
         ..default()
     };
+    let (field_canvas, _) =
+        simulated_canvas(&field_target, IVec2::new(0, -1), Left, &[Up, Right, Up]);
 
     assert_eq!(
         compare_fields(&field_target, &field_canvas),
-        MowResult::TooLittle
+        MowResult::TooMuch
     );
 }
 
 #[test]
-fn too_much_mowed() {
+fn report_counts_every_square() {
     let mut target_mowed = HashMap::new();
     target_mowed.insert((0, 0), true);
     target_mowed.insert((0, 1), true);
@@ -392,19 +828,71 @@ fn too_much_mowed() {
         mowed: target_mowed,
         ..default()
     };
-    let mut canvas_mowed = HashMap::new();
-    canvas_mowed.insert((0, 0), true);
-    canvas_mowed.insert((1, 1), true);
-    canvas_mowed.insert((1, 0), false);
+    let (field_canvas, _) = simulated_canvas(&field_target, IVec2::new(0, -1), Up, &[Up, Right]);
 
-    let field_canvas = Field {
-        size: UVec2::new(2, 2),
-        mowed: canvas_mowed,
+    let report = MowReport::new(&field_target, &field_canvas, 3, 3, Duration::ZERO, 0, None);
+
+    assert_eq!(report.result, MowResult::TooMuch);
+    assert_eq!((report.correct, report.wrong, report.missed), (1, 1, 1));
+    assert_eq!(report.stars, 0);
+}
+
+#[test]
+fn stars_for_commands_and_time() {
+    let mut target_mowed = HashMap::new();
+    target_mowed.insert((0, 0), true);
+    let field_target = Field {
+        size: UVec2::new(1, 2),
+        mowed: target_mowed,
         ..default()
     };
+    let (field_canvas, _) = simulated_canvas(&field_target, IVec2::new(0, 2), Down, &[Down]);
+
+    let report = |commands, seconds, hints| {
+        MowReport::new(
+            &field_target,
+            &field_canvas,
+            commands,
+            2,
+            Duration::from_secs(seconds),
+            hints,
+            None,
+        )
+    };
+
+    assert_eq!(report(2, 1, 0).accuracy, 100.0);
+    assert_eq!(report(2, 1, 0).stars, 3);
+    assert_eq!(report(3, 1, 0).stars, 2);
+    assert_eq!(report(3, 60, 0).stars, 1);
+    assert_eq!(report(2, 1, 1).stars, 2);
+    assert_eq!(report(2, 1, 5).stars, 1);
+}
+
+#[test]
+fn crashing_fails_the_board() {
+    let mut target_mowed = HashMap::new();
+    target_mowed.insert((1, 0), true);
+    let mut obstacles = HashMap::new();
+    obstacles.insert((1, 0), Obstacle::Rock);
+    let field_target = Field {
+        size: UVec2::new(2, 1),
+        mowed: target_mowed,
+        obstacles,
+        ..default()
+    };
+    let (field_canvas, crash) =
+        simulated_canvas(&field_target, IVec2::new(-1, 0), Right, &[Right, Right]);
+    let report =
+        |crash| MowReport::new(&field_target, &field_canvas, 2, 2, Duration::ZERO, 0, crash);
 
     assert_eq!(
-        compare_fields(&field_target, &field_canvas),
-        MowResult::TooMuch
+        crash,
+        Some(Crash {
+            reason: CrashReason::Obstacle,
+            command_index: 1,
+        })
     );
+    assert_eq!(report(None).result, MowResult::Perfect);
+    assert_eq!(report(crash).result, MowResult::Crashed(crash.unwrap()));
+    assert_eq!(report(crash).stars, 0);
 }
@@ -1,7 +1,17 @@
-use crate::field::{FIELD_MARGIN_SIZE, FIELD_SIZE, FIELD_THICKNESS};
+use crate::field::{
+    canvas_square_translation, Field, FIELD_MARGIN_SIZE, FIELD_SIZE, FIELD_THICKNESS,
+};
+use crate::level::{ActiveLevel, Level};
+use crate::program::{
+    expand, run, run_recorded, steer, Command, Condition, Procedure, Program, Steering,
+};
+use crate::puzzle::{is_in_bounds, Controls, Puzzle};
+use crate::simulation::{Crash, CrashReason, FieldSpec, HeaderWidth, Snapshot, TraceStep};
+use crate::solver::{hint, Hint};
 use crate::ui::{
     update_help_text, ArrowImage, CommandsContainerMarker, CountDownMarkerMilliSeconds,
-    CountDownMarkerSeconds, FontHandle, HelpTextContainer,
+    CountDownMarkerSeconds, FontHandle, HelpTextContainer, PlaybackSpeedTextMarker,
+    ProcedureContainerMarker,
 };
 use bevy::prelude::*;
 use bevy::utils::Instant;
@@ -13,7 +23,10 @@ use rand::{
     distributions::{Distribution, Standard},
     Rng,
 };
+use std::collections::VecDeque;
 use std::f32::consts::PI;
+use std::fmt;
+use std::time::Duration;
 
 pub struct HarvestorPlugin;
 
@@ -22,39 +35,144 @@ pub enum HarvestorState {
     AcceptingCommands,
     Running,
     Done,
+    /// Changing the level in the editor, see [`crate::editor::LevelEditor`]
+    Editing,
+}
+
+/// Other plugins react to key presses after `keyboard_input` handled them
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyboardInput;
+
+/// Whether a run goes on by itself, only changes while [`HarvestorState::Running`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RunState {
+    Playing,
+    Paused,
+    /// Runs one more command, then pauses
+    Stepping,
 }
 
 impl Plugin for HarvestorPlugin {
     fn build(&self, app: &mut App) {
         app.add_loopless_state(HarvestorState::AcceptingCommands)
+            .add_loopless_state(RunState::Playing)
             .add_event::<HarvestorCommandsClearedEvent>()
+            .add_event::<NextBoardEvent>()
+            .add_event::<HarvestorCrashedEvent>()
+            .add_event::<HarvestorMovedEvent>()
+            .init_resource::<PlaybackSpeed>()
+            .add_system(change_playback_speed.run_not_in_state(HarvestorState::Editing))
+            .add_system(highlight_crashed_command)
+            .add_enter_system(HarvestorState::Running, plan_run)
+            .add_enter_system(HarvestorState::Running, play_run)
+            .add_system(debug_run.run_in_state(HarvestorState::Running))
             .add_system(move_harvestor)
+            .add_system(highlight_running_command.after(move_harvestor))
+            .init_resource::<Timeline>()
+            .init_resource::<ResumeEditing>()
+            .add_system(scrub_timeline.run_in_state(HarvestorState::Done))
+            .add_system(resume_editing.run_in_state(HarvestorState::AcceptingCommands))
             .init_resource::<TimeSpentWaitingOnCommands>()
+            .init_resource::<CommandCount>()
+            .init_resource::<HintCount>()
             .add_enter_system(HarvestorState::AcceptingCommands, reset_time_waiting)
+            .add_enter_system(HarvestorState::AcceptingCommands, reset_hints)
+            .add_exit_system(HarvestorState::AcceptingCommands, stop_time_waiting)
             .add_system(update_count_down.run_in_state(HarvestorState::AcceptingCommands))
             // .register_inspectable::<Harvestor>()
             // .register_inspectable::<InputCommands>()
             .add_system(watch_havestor_finished_moves.before(move_harvestor))
-            .add_system(keyboard_input.before(move_harvestor))
+            .add_system(keyboard_input.label(KeyboardInput).before(move_harvestor))
+            .add_system(sync_command_strip.after(KeyboardInput))
+            .add_exit_system(HarvestorState::AcceptingCommands, hide_command_cursor)
+            .init_resource::<PathPreviewAssets>()
+            .add_system(
+                preview_path
+                    .run_in_state(HarvestorState::AcceptingCommands)
+                    .after(KeyboardInput),
+            )
+            .add_exit_system(HarvestorState::AcceptingCommands, hide_path_preview)
+            .add_system(show_hint.run_in_state(HarvestorState::AcceptingCommands))
+            .add_enter_system(HarvestorState::Running, clear_hint)
+            .init_resource::<ProgramText>()
+            .add_system(
+                type_program
+                    .run_in_state(HarvestorState::AcceptingCommands)
+                    .after(KeyboardInput),
+            )
+            .add_exit_system(HarvestorState::AcceptingCommands, stop_typing)
             .add_plugin(EasingsPlugin)
-            .add_enter_system(HarvestorState::AcceptingCommands, setup);
+            .add_enter_system(HarvestorState::AcceptingCommands, despawn_harvestor)
+            .add_system(setup.run_not_in_state(HarvestorState::Running));
     }
 }
 
 pub struct TimeSpentWaitingOnCommands {
     time_start: Instant,
+    time_end: Option<Instant>,
 }
 
 impl Default for TimeSpentWaitingOnCommands {
     fn default() -> Self {
         Self {
             time_start: Instant::now(),
+            time_end: None,
         }
     }
 }
 
-fn reset_time_waiting(mut time_waiting: ResMut<TimeSpentWaitingOnCommands>) {
-    time_waiting.time_start = Instant::now();
+impl TimeSpentWaitingOnCommands {
+    /// Time spent entering commands, stops counting once the harvestor starts running
+    pub fn elapsed(&self) -> Duration {
+        self.time_end.unwrap_or_else(Instant::now) - self.time_start
+    }
+}
+
+fn reset_time_waiting(
+    mut time_waiting: ResMut<TimeSpentWaitingOnCommands>,
+    resume_editing: Res<ResumeEditing>,
+) {
+    match (&resume_editing.0, time_waiting.time_end) {
+        // rewinding goes on with the same board, the clock picks up where the run stopped it
+        (Some(_), Some(time_end)) => time_waiting.time_start += Instant::now() - time_end,
+        (Some(_), None) => {}
+        (None, _) => time_waiting.time_start = Instant::now(),
+    }
+    time_waiting.time_end = None;
+}
+
+fn stop_time_waiting(mut time_waiting: ResMut<TimeSpentWaitingOnCommands>) {
+    time_waiting.time_end = Some(Instant::now());
+}
+
+/// Amount of commands the player entered before running the harvestor
+#[derive(Default)]
+pub struct CommandCount(pub usize);
+
+/// Hints the player asked for on this board, every hint costs a star
+#[derive(Default)]
+pub struct HintCount {
+    pub used: usize,
+    /// The commands when the last hint was shown, asking again without changing them shows the
+    /// same hint and doesn't cost another star
+    hinted_at: Option<Program>,
+}
+
+fn reset_hints(mut hint_count: ResMut<HintCount>, resume_editing: Res<ResumeEditing>) {
+    // rewinding goes on with the same board, so the hints still count
+    if resume_editing.0.is_none() {
+        *hint_count = HintCount::default();
+    }
+}
+
+/// The entered commands as text while the player types them, see [`type_program`]
+#[derive(Default)]
+pub struct ProgramText {
+    pub typing: Option<String>,
+}
+
+fn stop_typing(mut program_text: ResMut<ProgramText>) {
+    program_text.typing = None;
 }
 
 fn update_count_down(
@@ -74,7 +192,7 @@ fn update_count_down(
     >,
     time_waiting: Res<TimeSpentWaitingOnCommands>,
 ) {
-    let time_since = Instant::now() - time_waiting.time_start;
+    let time_since = time_waiting.elapsed();
     let millis = time_since.as_millis().rem_euclid(1000) / 10;
 
     seconds_q.iter_mut().for_each(|mut text| {
@@ -87,19 +205,54 @@ fn update_count_down(
 
 pub struct HarvestorCommandsClearedEvent;
 
+/// Sent when the player is done looking at the result and wants to move on
+pub struct NextBoardEvent;
+
 const HARVESTOR_SCALE: f32 = 0.0004;
+/// Seconds a step takes at normal playback speed, see [`PlaybackSpeed`]
 const HARVESTOR_MOVEMENT_TIME: f32 = 0.25;
+/// How far the harvestor tips back while its header is raised
+const HEADER_TILT: f32 = PI / 24.0;
 
 #[derive(Component, Inspectable, Default)]
 pub struct Harvestor {
     pub position: IVec2,
-    direction: HarvestorCommands,
+    pub direction: HarvestorCommands,
     #[inspectable(ignore)]
     moving: Option<Timer>,
-    turning: bool,
+    /// The step of the trace that is being animated
+    #[inspectable(ignore)]
+    step: Option<TraceStep>,
+    /// Set once it drove off the field or into an obstacle, it stays there until the next board
+    #[inspectable(ignore)]
+    pub crash: Option<Crash>,
+    /// Squares it drives onto while the header is raised are left standing
+    pub header_raised: bool,
+    #[inspectable(ignore)]
+    pub header_width: HeaderWidth,
+}
+
+impl Harvestor {
+    /// What runs of this harvestor on `field` depend on
+    fn field_spec(&self, field: &Field) -> FieldSpec {
+        FieldSpec {
+            header_width: self.header_width,
+            ..field.spec()
+        }
+    }
+}
+
+pub struct HarvestorCrashedEvent(pub Crash);
+
+/// Sent when the harvestor arrived on a square of the field
+pub struct HarvestorMovedEvent {
+    pub position: IVec2,
+    pub facing: HarvestorCommands,
+    pub header_raised: bool,
+    pub header_width: HeaderWidth,
 }
 
-#[derive(Debug, Inspectable, Default, PartialEq, Eq, Clone)]
+#[derive(Debug, Inspectable, Default, PartialEq, Eq, Hash, Clone)]
 pub enum HarvestorCommands {
     #[default]
     Up,
@@ -108,6 +261,31 @@ pub enum HarvestorCommands {
     Right,
 }
 
+impl HarvestorCommands {
+    /// The direction a quarter turn to the left, as seen from above the field
+    pub fn turned_left(&self) -> HarvestorCommands {
+        match self {
+            HarvestorCommands::Up => HarvestorCommands::Left,
+            HarvestorCommands::Left => HarvestorCommands::Down,
+            HarvestorCommands::Down => HarvestorCommands::Right,
+            HarvestorCommands::Right => HarvestorCommands::Up,
+        }
+    }
+
+    pub fn turned_right(&self) -> HarvestorCommands {
+        self.turned_left().reversed()
+    }
+
+    pub fn reversed(&self) -> HarvestorCommands {
+        match self {
+            HarvestorCommands::Up => HarvestorCommands::Down,
+            HarvestorCommands::Down => HarvestorCommands::Up,
+            HarvestorCommands::Left => HarvestorCommands::Right,
+            HarvestorCommands::Right => HarvestorCommands::Left,
+        }
+    }
+}
+
 impl Distribution<HarvestorCommands> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> HarvestorCommands {
         match rng.gen_range(0..=3) {
@@ -121,51 +299,248 @@ impl Distribution<HarvestorCommands> for Standard {
 
 #[derive(Component, Inspectable, Default)]
 struct InputCommands {
-    commands: Vec<HarvestorCommands>,
-    clear: bool,
+    #[inspectable(ignore)]
+    main: CommandQueue,
+    #[inspectable(ignore)]
+    procedures: [CommandQueue; 2],
+    /// The procedure whose row is being edited, the main commands when `None`
+    #[inspectable(ignore)]
+    editing: Option<Procedure>,
+    /// What is left to animate of the simulated commands
+    #[inspectable(ignore)]
+    steps: VecDeque<TraceStep>,
 }
 
+impl InputCommands {
+    fn queue(&self, row: Option<Procedure>) -> &CommandQueue {
+        match row {
+            None => &self.main,
+            Some(procedure) => &self.procedures[procedure as usize],
+        }
+    }
+
+    fn editing_mut(&mut self) -> &mut CommandQueue {
+        match self.editing {
+            None => &mut self.main,
+            Some(procedure) => &mut self.procedures[procedure as usize],
+        }
+    }
+
+    /// Goes from the main commands through every procedure and back
+    fn edit_next_row(&mut self) {
+        self.editing = match self.editing {
+            None => Some(Procedure::P1),
+            Some(Procedure::P1) => Some(Procedure::P2),
+            Some(Procedure::P2) => None,
+        };
+    }
+
+    /// Replaces the commands of every row, the cursors end up behind them
+    fn set_program(&mut self, program: Program) {
+        self.main = CommandQueue::new(program.main);
+        self.procedures = program.procedures.map(CommandQueue::new);
+    }
+
+    fn program(&self) -> Program {
+        Program {
+            main: self.main.commands.clone(),
+            procedures: [
+                self.procedures[0].commands.clone(),
+                self.procedures[1].commands.clone(),
+            ],
+        }
+    }
+}
+
+/// The commands of one row in the command strip
+#[derive(Default)]
+struct CommandQueue {
+    commands: Vec<Command>,
+    /// Where the next entered command goes, from 0 up to and including the amount of commands
+    cursor: usize,
+    /// Where the group started with `[` begins, a digit turns it into a repeat block
+    group_start: Option<usize>,
+}
+
+impl CommandQueue {
+    fn new(commands: Vec<Command>) -> Self {
+        CommandQueue {
+            cursor: commands.len(),
+            commands,
+            group_start: None,
+        }
+    }
+
+    fn insert(&mut self, command: Command) {
+        self.commands.insert(self.cursor, command);
+        self.cursor += 1;
+    }
+
+    /// Removes the command before the cursor, like Backspace
+    fn remove_before_cursor(&mut self) {
+        self.group_start = None;
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.commands.remove(self.cursor);
+        }
+    }
+
+    /// Removes the command after the cursor, like Delete
+    fn remove_after_cursor(&mut self) {
+        self.group_start = None;
+        if self.cursor < self.commands.len() {
+            self.commands.remove(self.cursor);
+        }
+    }
+
+    fn move_cursor(&mut self, cursor: usize) {
+        self.group_start = None;
+        self.cursor = cursor.min(self.commands.len());
+    }
+
+    fn start_group(&mut self) {
+        self.group_start = Some(self.cursor);
+    }
+
+    /// Takes the group started with `[` out of the commands, or else the command before the
+    /// cursor
+    fn take_block(&mut self) -> Option<(usize, Vec<Command>)> {
+        let start = match self.group_start.take() {
+            Some(start) if start < self.cursor => start,
+            _ if self.cursor > 0 => self.cursor - 1,
+            _ => return None,
+        };
+        let body = self.commands.drain(start..self.cursor).collect();
+        self.cursor = start;
+
+        Some((start, body))
+    }
+
+    /// Puts the group started with `[`, or else the command before the cursor, into a block
+    fn wrap(&mut self, block: impl FnOnce(Vec<Command>) -> Command) {
+        if let Some((_, body)) = self.take_block() {
+            self.insert(block(body));
+        }
+    }
+
+    /// Repeats the group started with `[`, or else the command before the cursor. Changes the
+    /// count of a repeat block before the cursor instead, and unpacks it when repeated once.
+    fn repeat(&mut self, times: u32) {
+        let grouped = matches!(self.group_start, Some(start) if start < self.cursor);
+        if let (false, Some(Command::Repeat(count, body))) =
+            (grouped, self.commands[..self.cursor].last_mut())
+        {
+            if times > 1 {
+                *count = times;
+            } else {
+                let body = std::mem::take(body);
+                let start = self.cursor - 1;
+                self.cursor = start + body.len();
+                self.commands.splice(start..start + 1, body);
+            }
+            return;
+        }
+
+        if times > 1 {
+            self.wrap(|body| Command::Repeat(times, body));
+        } else {
+            self.group_start = None;
+        }
+    }
+
+    /// Makes the group started with `[`, or else the command before the cursor, what the `if`
+    /// right before it runs when its condition doesn't hold
+    fn otherwise(&mut self) {
+        let group_start = self.group_start;
+        let (start, body) = match self.take_block() {
+            Some(block) => block,
+            None => return,
+        };
+
+        match start
+            .checked_sub(1)
+            .and_then(|if_index| self.commands.get_mut(if_index))
+        {
+            Some(Command::If(_, _, otherwise)) if otherwise.is_empty() => *otherwise = body,
+            _ => {
+                // there's no `if` to add them to, so they go back where they were
+                self.cursor = start + body.len();
+                self.commands.splice(start..start, body);
+                self.group_start = group_start;
+            }
+        }
+    }
+
+    /// Turns the condition of the `if` or `while` before the cursor around
+    fn negate(&mut self) {
+        if let Some(Command::If(condition, ..) | Command::While(condition, _)) =
+            self.commands[..self.cursor].last_mut()
+        {
+            *condition = condition.clone().negated();
+        }
+    }
+}
+
+fn despawn_harvestor(mut commands: Commands, harvestor_q: Query<Entity, With<Harvestor>>) {
+    harvestor_q.iter().for_each(|e| {
+        commands.entity(e).despawn_recursive();
+    });
+}
+
+/// Spawns the harvestor at the start of the active level as soon as it's loaded, or again after
+/// the editor despawned it
 fn setup(
     mut commands: Commands,
     ass: Res<AssetServer>,
-    harvestor_q: Query<Entity, With<Harvestor>>,
+    harvestor_q: Query<(), With<Harvestor>>,
+    active_level: Res<ActiveLevel>,
+    levels: Res<Assets<Level>>,
 ) {
-    harvestor_q.iter().for_each(|e| {
-        commands.entity(e).despawn_recursive();
-    });
-    spawn(&mut commands, &ass, IVec2::new(0, -1));
+    if !harvestor_q.is_empty() {
+        return;
+    }
+    if let Some(level) = levels.get(&active_level.handle) {
+        spawn(&mut commands, &ass, &level.puzzle);
+    }
 }
 
-fn spawn(commands: &mut Commands, ass: &Res<AssetServer>, position: IVec2) {
+fn spawn(commands: &mut Commands, ass: &Res<AssetServer>, puzzle: &Puzzle) {
     let gltf: Handle<Scene> = ass.load("harvestor.glb#Scene0");
 
-    let start_pos = Vec3::new(-1.0, FIELD_THICKNESS + 0.05, -0.2);
+    let position = puzzle.start;
+    let direction = puzzle.facing.clone();
     commands
         .spawn_bundle(SceneBundle {
             scene: gltf,
-            transform: Transform::from_xyz(start_pos.x, start_pos.y, start_pos.z)
-                .with_scale(Vec3::from([
-                    HARVESTOR_SCALE,
-                    HARVESTOR_SCALE,
-                    HARVESTOR_SCALE,
-                ]))
-                .looking_at(
-                    command_to_direction(&HarvestorCommands::Left) + start_pos,
-                    Vec3::Y,
-                ),
-
+            transform: harvestor_transform(puzzle.size, position, &direction),
             ..Default::default()
         })
         .insert(Harvestor {
             position,
-            direction: HarvestorCommands::Left,
+            direction,
             moving: None,
-            turning: false,
+            step: None,
+            crash: None,
+            header_raised: false,
+            header_width: puzzle.header_width,
         })
-        .insert(InputCommands {
-            commands: vec![],
-            clear: false,
-        });
+        .insert(InputCommands::default());
+}
+
+/// The harvestor standing on a square of the canvas field, facing `direction`
+fn harvestor_transform(size: UVec2, position: IVec2, direction: &HarvestorCommands) -> Transform {
+    let translation =
+        canvas_square_translation(size, position) + Vec3::Y * (FIELD_THICKNESS + 0.05);
+
+    Transform::from_translation(translation)
+        .with_scale(Vec3::splat(HARVESTOR_SCALE))
+        .looking_at(command_to_direction(direction) + translation, Vec3::Y)
+}
+
+/// Tips the harvestor back when the header is raised and forward again when it's lowered
+fn tilt_header(tf: &mut Transform, raised: bool) {
+    let angle = if raised { HEADER_TILT } else { -HEADER_TILT };
+    tf.rotate(Quat::from_axis_angle(tf.local_x(), angle));
 }
 
 pub fn command_to_direction(input: &HarvestorCommands) -> Vec3 {
@@ -177,9 +552,17 @@ pub fn command_to_direction(input: &HarvestorCommands) -> Vec3 {
     }
 }
 
+/// How a square position on the field changes when moving in this direction
+pub fn command_to_grid_offset(input: &HarvestorCommands) -> IVec2 {
+    let direction = command_to_direction(input);
+
+    IVec2::new(-direction.x as i32, direction.z as i32)
+}
+
 pub fn watch_havestor_finished_moves(
     mut harvestor_q: Query<&mut Harvestor>,
     time: Res<Time>,
+    mut ev_moved: EventWriter<HarvestorMovedEvent>,
     mut ev_commands_cleared: EventWriter<HarvestorCommandsClearedEvent>,
     state: Res<CurrentState<HarvestorState>>,
 ) {
@@ -190,79 +573,378 @@ pub fn watch_havestor_finished_moves(
 
             if timer.just_finished() {
                 h.moving = None;
-                if !h.turning {
-                    let a = command_to_direction(&h.direction);
-
-                    h.position.x -= a.x as i32;
-                    h.position.y += a.z as i32;
+                if let Some(TraceStep::Move { to, .. }) = h.step.take() {
+                    h.position = to;
+                    ev_moved.send(HarvestorMovedEvent {
+                        position: to,
+                        facing: h.direction.clone(),
+                        header_raised: h.header_raised,
+                        header_width: h.header_width,
+                    });
+                }
 
-                    if state.0 == HarvestorState::Done {
-                        ev_commands_cleared.send(HarvestorCommandsClearedEvent);
-                    }
+                if state.0 == HarvestorState::Done {
+                    ev_commands_cleared.send(HarvestorCommandsClearedEvent);
                 }
-                h.turning = false;
             }
         }
     });
 }
 
+/// Simulates the entered commands once the harvestor starts running, the harvestor then only
+/// animates the resulting trace
+fn plan_run(
+    mut harvestor_q: Query<(&Harvestor, &mut InputCommands)>,
+    field_q: Query<&Field>,
+    mut timeline: ResMut<Timeline>,
+) {
+    let field = match field_q.iter().next() {
+        Some(field) => field,
+        None => return,
+    };
+
+    harvestor_q.iter_mut().for_each(|(h, mut input_commands)| {
+        let trace = run_recorded(
+            h.position,
+            h.direction.clone(),
+            &input_commands.program(),
+            &h.field_spec(field),
+        );
+        input_commands.steps = trace.steps.into();
+        *timeline = Timeline {
+            snapshots: trace.timeline,
+            shown: None,
+        };
+    });
+}
+
+/// Snapshots of the last run, a finished run can be rewound to any of them
+#[derive(Default)]
+pub struct Timeline {
+    pub snapshots: Vec<Snapshot>,
+    /// The snapshot the run is rewound to, `None` while it plays out
+    pub shown: Option<usize>,
+}
+
+/// The commands to go on editing after rewinding a run, with where the cursor goes
+#[derive(Default)]
+struct ResumeEditing(Option<(Program, usize)>);
+
+/// `Left` and `Right` rewind and replay a finished run one command at a time, `Enter` goes back
+/// to entering commands on the same board with the cursor behind the command that ran last
+#[allow(clippy::too_many_arguments)]
+fn scrub_timeline(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut timeline: ResMut<Timeline>,
+    mut resume_editing: ResMut<ResumeEditing>,
+    mut harvestor_q: Query<(&mut Harvestor, &mut Transform, &InputCommands)>,
+    field_q: Query<&Field>,
+    help_ui_container_q: Query<Entity, With<HelpTextContainer>>,
+    font: Res<FontHandle>,
+) {
+    let (mut h, mut tf, input_commands) = match harvestor_q.iter_mut().next() {
+        Some(harvestor) => harvestor,
+        None => return,
+    };
+    let last = match timeline.snapshots.len().checked_sub(1) {
+        Some(last) => last,
+        None => return,
+    };
+    if h.moving.is_some() {
+        return;
+    }
+    let shown = timeline.shown.unwrap_or(last);
+
+    if keys.just_released(KeyCode::Return) {
+        let cursor = timeline.snapshots[shown]
+            .command_index
+            .map_or(0, |index| index + 1);
+        resume_editing.0 = Some((input_commands.program(), cursor));
+        commands.insert_resource(NextState(HarvestorState::AcceptingCommands));
+        return;
+    }
+
+    let shown = if keys.just_released(KeyCode::Left) {
+        shown.saturating_sub(1)
+    } else if keys.just_released(KeyCode::Right) {
+        (shown + 1).min(last)
+    } else {
+        return;
+    };
+    timeline.shown = Some(shown);
+
+    let snapshot = &timeline.snapshots[shown];
+    if let Some(field) = field_q.iter().next() {
+        *tf = harvestor_transform(field.size(), snapshot.position, &snapshot.facing);
+        if snapshot.header_raised {
+            tilt_header(&mut tf, true);
+        }
+    }
+    h.position = snapshot.position;
+    h.direction = snapshot.facing.clone();
+    h.header_raised = snapshot.header_raised;
+
+    let position = match snapshot.command_index {
+        Some(index) => format!(
+            "Command {} of {}",
+            index + 1,
+            input_commands.program().main.len()
+        ),
+        None => "Start".to_string(),
+    };
+    let e = help_ui_container_q.single();
+    update_help_text(
+        &font,
+        &mut commands,
+        e,
+        &format!(
+            "{}. Left and Right rewind and replay, Enter edits from here",
+            position
+        ),
+    );
+}
+
+/// Puts back the commands after rewinding, once the harvestor is spawned again
+fn resume_editing(
+    mut resume_editing: ResMut<ResumeEditing>,
+    mut query: Query<&mut InputCommands, Added<InputCommands>>,
+) {
+    if let Some(mut input_commands) = query.iter_mut().next() {
+        if let Some((program, cursor)) = resume_editing.0.take() {
+            input_commands.set_program(program);
+            input_commands.main.move_cursor(cursor);
+        }
+    }
+}
+
+fn play_run(mut commands: Commands) {
+    commands.insert_resource(NextState(RunState::Playing));
+}
+
+const PAUSED_HELP_TEXT: &str = "Paused, press N to run the next command or P to continue";
+
+/// `P` pauses a run and continues it, `N` runs one more command and pauses again
+fn debug_run(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    run_state: Res<CurrentState<RunState>>,
+    help_ui_container_q: Query<Entity, With<HelpTextContainer>>,
+    font: Res<FontHandle>,
+) {
+    let (next, help_text) = if keys.just_released(KeyCode::P) {
+        match run_state.0 {
+            RunState::Playing => (RunState::Paused, PAUSED_HELP_TEXT),
+            RunState::Paused | RunState::Stepping => {
+                (RunState::Playing, "Harvesting... press P to pause")
+            }
+        }
+    } else if keys.just_released(KeyCode::N) {
+        (RunState::Stepping, PAUSED_HELP_TEXT)
+    } else {
+        return;
+    };
+
+    commands.insert_resource(NextState(next));
+    let e = help_ui_container_q.single();
+    update_help_text(&font, &mut commands, e, help_text);
+}
+
+/// Turns the harvestor for a step or marks it crashed, returns where the step takes it
+fn start_step(h: &mut Harvestor, tf: &Transform, step: &TraceStep) -> Transform {
+    // moves go towards the square they drive onto, which is behind the harvestor when it backs up
+    let vector_distance = match step {
+        TraceStep::Turn { direction, .. } => command_to_direction(direction),
+        TraceStep::Move { to, .. } | TraceStep::Crash { to, .. } => {
+            let offset = *to - h.position;
+            Vec3::new(-offset.x as f32, 0.0, offset.y as f32)
+        }
+        TraceStep::Header { .. } => Vec3::ZERO,
+    } * (FIELD_SIZE + FIELD_MARGIN_SIZE);
+    let mut new_tf = *tf;
+    match step {
+        TraceStep::Turn { direction, .. } => {
+            new_tf.look_at(vector_distance + tf.translation, Vec3::Y);
+            if h.header_raised {
+                tilt_header(&mut new_tf, true);
+            }
+            h.direction = direction.clone();
+        }
+        TraceStep::Header { raised, .. } => {
+            tilt_header(&mut new_tf, *raised);
+            h.header_raised = *raised;
+        }
+        TraceStep::Move { .. } => new_tf.translation += vector_distance,
+        TraceStep::Crash { crash, .. } => {
+            match crash.reason {
+                // bump into the obstacle and stop there
+                CrashReason::Obstacle => new_tf.translation += vector_distance * 0.3,
+                // tip over the edge of the field
+                CrashReason::OutOfBounds => {
+                    new_tf.translation += vector_distance * 0.6 - Vec3::Y * FIELD_SIZE;
+                    new_tf.rotate(Quat::from_axis_angle(tf.local_x(), -PI / 4.0));
+                }
+            }
+            h.crash = Some(*crash);
+        }
+    }
+
+    new_tf
+}
+
+#[allow(clippy::too_many_arguments)]
 fn move_harvestor(
     mut commands: Commands,
     mut harvestor_q: Query<
         (Entity, &Transform, &mut InputCommands, &mut Harvestor),
         Without<EasingComponent<Transform>>,
     >,
+    mut ev_crashed: EventWriter<HarvestorCrashedEvent>,
+    mut ev_moved: EventWriter<HarvestorMovedEvent>,
+    mut ev_commands_cleared: EventWriter<HarvestorCommandsClearedEvent>,
+    state: Res<CurrentState<HarvestorState>>,
+    run_state: Res<CurrentState<RunState>>,
+    playback_speed: Res<PlaybackSpeed>,
 ) {
+    if state.0 != HarvestorState::Running || run_state.0 == RunState::Paused {
+        return;
+    }
     harvestor_q
         .iter_mut()
         .for_each(|(e, tf, mut input_commands, mut h)| {
-            if !input_commands.clear || input_commands.commands.is_empty() {
-                return;
-            }
-
             if h.moving.is_some() {
                 return;
             }
-            if let Some(cmd) = input_commands.commands.get(0) {
-                let dir = command_to_direction(cmd);
-                let vector_distance = dir * (FIELD_SIZE + FIELD_MARGIN_SIZE);
-                if h.direction == *cmd {
-                    let mut new_tf = *tf;
-                    new_tf.translation += vector_distance;
-                    let easing_component = tf.ease_to(
-                        new_tf,
-                        QuadraticIn,
-                        bevy_easings::EasingType::Once {
-                            duration: std::time::Duration::from_secs_f32(HARVESTOR_MOVEMENT_TIME),
-                        },
-                    );
 
-                    commands.entity(e).insert(easing_component);
-                    input_commands.commands.remove(0);
-                } else {
-                    let mut new_tf = *tf;
-                    new_tf.look_at(vector_distance + tf.translation, Vec3::Y);
-                    let a = tf.ease_to(
-                        new_tf,
-                        QuadraticIn,
-                        EasingType::Once {
-                            duration: std::time::Duration::from_secs_f32(HARVESTOR_MOVEMENT_TIME),
-                        },
-                    );
-                    commands.entity(e).insert(a);
-                    h.direction = cmd.clone();
-                    h.turning = true;
+            let mut tf = *tf;
+            loop {
+                let step = match input_commands.steps.pop_front() {
+                    Some(step) => step,
+                    None => {
+                        // nothing to run, so there's nothing to wait for either
+                        commands.insert_resource(NextState(HarvestorState::Done));
+                        ev_commands_cleared.send(HarvestorCommandsClearedEvent);
+                        return;
+                    }
                 };
-                h.moving = Timer::from_seconds(HARVESTOR_MOVEMENT_TIME, false).into();
-            }
 
-            if input_commands.commands.is_empty() {
-                input_commands.clear = false;
-                commands.insert_resource(NextState(HarvestorState::Done));
+                let new_tf = start_step(&mut h, &tf, &step);
+                if let TraceStep::Crash { crash, .. } = &step {
+                    ev_crashed.send(HarvestorCrashedEvent(*crash));
+                }
+                // a command in a new direction turns first, so it ends with the move after that
+                let ends_command = match (&step, input_commands.steps.front()) {
+                    (
+                        TraceStep::Turn { command_index, .. },
+                        Some(next @ (TraceStep::Move { .. } | TraceStep::Crash { .. })),
+                    ) => next.command_index() != *command_index,
+                    _ => true,
+                };
+                let pauses = ends_command && run_state.0 == RunState::Stepping;
+                if pauses {
+                    commands.insert_resource(NextState(RunState::Paused));
+                }
+                let last = input_commands.steps.is_empty();
+                if last {
+                    commands.insert_resource(NextState(HarvestorState::Done));
+                }
+
+                match playback_speed.step_duration() {
+                    Some(duration) => {
+                        let easing_component =
+                            tf.ease_to(new_tf, QuadraticIn, EasingType::Once { duration });
+                        commands.entity(e).insert(easing_component);
+                        h.moving = Some(Timer::new(duration, false));
+                        h.step = Some(step);
+                        return;
+                    }
+                    None if pauses || last => {
+                        // finishes the step the same way as an animated one, without waiting
+                        commands.entity(e).insert(new_tf);
+                        h.moving = Some(Timer::new(Duration::ZERO, false));
+                        h.step = Some(step);
+                        return;
+                    }
+                    None => {
+                        tf = new_tf;
+                        if let TraceStep::Move { to, .. } = step {
+                            h.position = to;
+                            ev_moved.send(HarvestorMovedEvent {
+                                position: to,
+                                facing: h.direction.clone(),
+                                header_raised: h.header_raised,
+                                header_width: h.header_width,
+                            });
+                        }
+                    }
+                }
             }
         });
 }
 
+/// How fast runs play out, `-` and `=` go through [`PLAYBACK_SPEEDS`]
+pub struct PlaybackSpeed {
+    index: usize,
+}
+
+/// How many times faster than normal a run plays out, `None` skips the animation
+const PLAYBACK_SPEEDS: [Option<f32>; 6] =
+    [Some(0.5), Some(1.0), Some(2.0), Some(4.0), Some(8.0), None];
+
+impl Default for PlaybackSpeed {
+    fn default() -> Self {
+        PlaybackSpeed { index: 1 }
+    }
+}
+
+impl PlaybackSpeed {
+    /// How long the harvestor takes for a step, `None` when it doesn't animate at all
+    pub fn step_duration(&self) -> Option<Duration> {
+        PLAYBACK_SPEEDS[self.index]
+            .map(|speed| Duration::from_secs_f32(HARVESTOR_MOVEMENT_TIME / speed))
+    }
+
+    fn faster(&mut self) {
+        self.index = (self.index + 1).min(PLAYBACK_SPEEDS.len() - 1);
+    }
+
+    fn slower(&mut self) {
+        self.index = self.index.saturating_sub(1);
+    }
+}
+
+impl fmt::Display for PlaybackSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match PLAYBACK_SPEEDS[self.index] {
+            Some(speed) => write!(f, "{}x", speed),
+            None => write!(f, "instant"),
+        }
+    }
+}
+
+/// `-` and `=` slow runs down and speed them up, also while one is playing
+fn change_playback_speed(
+    keys: Res<Input<KeyCode>>,
+    mut playback_speed: ResMut<PlaybackSpeed>,
+    program_text: Res<ProgramText>,
+    mut text_q: Query<&mut Text, With<PlaybackSpeedTextMarker>>,
+) {
+    if program_text.typing.is_some() {
+        return;
+    }
+    if keys.just_released(KeyCode::Minus) {
+        playback_speed.slower();
+    } else if keys.just_released(KeyCode::Equals) {
+        playback_speed.faster();
+    } else if !playback_speed.is_added() {
+        return;
+    }
+
+    text_q.iter_mut().for_each(|mut text| {
+        text.sections[0].value = format!("Speed {}", *playback_speed);
+    });
+}
+
 #[allow(clippy::too_many_arguments)]
 fn keyboard_input(
     mut commands: Commands,
@@ -270,52 +952,167 @@ fn keyboard_input(
     mut query: Query<&mut InputCommands>,
     ui: Query<Entity, With<CommandsContainerMarker>>,
     help_ui_container_q: Query<Entity, With<HelpTextContainer>>,
-    arrow_image: Res<ArrowImage>,
     font: Res<FontHandle>,
     state: Res<CurrentState<HarvestorState>>,
+    program_text: Res<ProgramText>,
+    active_level: Res<ActiveLevel>,
+    levels: Res<Assets<Level>>,
+    mut ev_next_board: EventWriter<NextBoardEvent>,
 ) {
-    let mut command = None;
-    if state.0 == HarvestorState::AcceptingCommands {
-        if keys.just_released(KeyCode::Left) {
-            command = Some(HarvestorCommands::Left);
+    let mut arrow = None;
+    if state.0 == HarvestorState::AcceptingCommands && program_text.typing.is_none() {
+        let puzzle = levels.get(&active_level.handle).map(|level| &level.puzzle);
+        let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+        // shift + left and right move the cursor instead of entering a command
+        if shift && (keys.just_released(KeyCode::Left) || keys.just_released(KeyCode::Right)) {
+            let left = keys.just_released(KeyCode::Left);
+            query.iter_mut().for_each(|mut ic| {
+                let queue = ic.editing_mut();
+                let cursor = if left {
+                    queue.cursor.saturating_sub(1)
+                } else {
+                    queue.cursor + 1
+                };
+                queue.move_cursor(cursor);
+            });
+        } else if keys.just_released(KeyCode::Home) {
+            query
+                .iter_mut()
+                .for_each(|mut ic| ic.editing_mut().move_cursor(0));
+        } else if keys.just_released(KeyCode::End) {
+            query
+                .iter_mut()
+                .for_each(|mut ic| ic.editing_mut().move_cursor(usize::MAX));
+        } else if keys.just_released(KeyCode::Back) {
+            query
+                .iter_mut()
+                .for_each(|mut ic| ic.editing_mut().remove_before_cursor());
+        } else if keys.just_released(KeyCode::Delete) {
+            query
+                .iter_mut()
+                .for_each(|mut ic| ic.editing_mut().remove_after_cursor());
+        } else if keys.just_released(KeyCode::LBracket) {
+            query
+                .iter_mut()
+                .for_each(|mut ic| ic.editing_mut().start_group());
+        } else if let Some(times) = released_digit(&keys) {
+            query
+                .iter_mut()
+                .for_each(|mut ic| ic.editing_mut().repeat(times));
+        } else if keys.just_released(KeyCode::I) {
+            query.iter_mut().for_each(|mut ic| {
+                ic.editing_mut()
+                    .wrap(|body| Command::If(Condition::AheadUnmowed, body, vec![]))
+            });
+        } else if keys.just_released(KeyCode::O) {
+            query
+                .iter_mut()
+                .for_each(|mut ic| ic.editing_mut().otherwise());
+        } else if keys.just_released(KeyCode::W) {
+            query.iter_mut().for_each(|mut ic| {
+                ic.editing_mut()
+                    .wrap(|body| Command::While(Condition::AtEdge.negated(), body))
+            });
+        } else if keys.just_released(KeyCode::N) {
+            query
+                .iter_mut()
+                .for_each(|mut ic| ic.editing_mut().negate());
+        } else if keys.just_released(KeyCode::Tab) {
+            query.iter_mut().for_each(|mut ic| ic.edit_next_row());
+        } else if let Some(procedure) = released_procedure(&keys) {
+            query
+                .iter_mut()
+                .for_each(|mut ic| ic.editing_mut().insert(Command::Call(procedure)));
+        } else if let Some(header) = released_header(&keys) {
+            for mut ic in query.iter_mut() {
+                // the commands already toggle the header as often as allowed when they run
+                let at_limit = puzzle.and_then(|puzzle| {
+                    let allowed = puzzle.header_toggles?;
+                    let used = run(
+                        puzzle.start,
+                        puzzle.facing.clone(),
+                        &ic.program(),
+                        &puzzle.field_spec(),
+                    )
+                    .header_toggles();
+                    if used >= allowed {
+                        Some(allowed)
+                    } else {
+                        None
+                    }
+                });
+                match at_limit {
+                    Some(allowed) => {
+                        let e = help_ui_container_q.single();
+                        update_help_text(
+                            &font,
+                            &mut commands,
+                            e,
+                            &format!("This board allows {} header toggles", allowed),
+                        );
+                    }
+                    None => ic.editing_mut().insert(header.clone()),
+                }
+            }
+        } else if keys.just_released(KeyCode::Left) {
+            arrow = Some(HarvestorCommands::Left);
         } else if keys.just_released(KeyCode::Right) {
-            command = Some(HarvestorCommands::Right);
+            arrow = Some(HarvestorCommands::Right);
         } else if keys.just_released(KeyCode::Up) {
-            command = Some(HarvestorCommands::Up);
+            arrow = Some(HarvestorCommands::Up);
         } else if keys.just_released(KeyCode::Down) {
-            command = Some(HarvestorCommands::Down);
+            arrow = Some(HarvestorCommands::Down);
         };
-        if let Some(command) = command {
+        let controls = puzzle.map(|puzzle| puzzle.controls).unwrap_or_default();
+        if let Some(command) = arrow.and_then(|arrow| controls.command(arrow)) {
             // should update help text
             let mut update_help = false;
             query.iter().take(1).for_each(|h| {
-                if h.commands.is_empty() {
+                if h.program().size() == 0 {
                     update_help = true;
                 }
             });
 
             if update_help {
                 let e = help_ui_container_q.single();
-                update_help_text(&font, &mut commands, e, "Press Enter to execute commands");
+                update_help_text(
+                    &font,
+                    &mut commands,
+                    e,
+                    "Press Enter to execute commands, Backspace to remove one",
+                );
             }
 
             query.iter_mut().for_each(|mut ic| {
-                ic.commands.push(command.clone());
+                ic.editing_mut().insert(command.clone());
             });
-
-            let command_ui_entity = ui.single();
-            spawn_image_command_ui(&arrow_image, &mut commands, command_ui_entity, &command);
         }
 
         if keys.just_released(KeyCode::Return) {
             let e = help_ui_container_q.single();
-            update_help_text(&font, &mut commands, e, "Harvesting...");
-
-            query.iter_mut().for_each(|mut ic| {
-                commands.insert_resource(NextState(HarvestorState::Running));
+            let excess_header_toggles = query
+                .iter()
+                .next()
+                .and_then(|ic| puzzle?.excess_header_toggles(&ic.program()));
+            match excess_header_toggles {
+                Some((used, allowed)) => update_help_text(
+                    &font,
+                    &mut commands,
+                    e,
+                    &format!(
+                        "This board allows {} header toggles, these commands make {}",
+                        allowed, used
+                    ),
+                ),
+                None => {
+                    update_help_text(&font, &mut commands, e, "Harvesting... press P to pause");
 
-                ic.clear = true;
-            });
+                    query.iter().for_each(|ic| {
+                        commands.insert_resource(NextState(HarvestorState::Running));
+                        commands.insert_resource(CommandCount(ic.program().size()));
+                    });
+                }
+            }
         }
     }
     if state.0 == HarvestorState::Done && keys.just_released(KeyCode::Space) {
@@ -323,27 +1120,737 @@ fn keyboard_input(
 
         let mut command_ui_parent = commands.entity(command_ui_entity);
         command_ui_parent.despawn_descendants();
+        ev_next_board.send(NextBoardEvent);
         commands.insert_resource(NextState(HarvestorState::AcceptingCommands));
     }
 }
 
-fn spawn_image_command_ui(
+const DIGIT_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+fn released_digit(keys: &Input<KeyCode>) -> Option<u32> {
+    DIGIT_KEYS
+        .iter()
+        .position(|key| keys.just_released(*key))
+        .map(|index| index as u32 + 1)
+}
+
+/// `F1` and `F2` call a procedure
+fn released_procedure(keys: &Input<KeyCode>) -> Option<Procedure> {
+    if keys.just_released(KeyCode::F1) {
+        Some(Procedure::P1)
+    } else if keys.just_released(KeyCode::F2) {
+        Some(Procedure::P2)
+    } else {
+        None
+    }
+}
+
+/// `Page Up` raises the header, `Page Down` lowers it
+fn released_header(keys: &Input<KeyCode>) -> Option<Command> {
+    if keys.just_released(KeyCode::PageUp) {
+        Some(Command::RaiseHeader)
+    } else if keys.just_released(KeyCode::PageDown) {
+        Some(Command::LowerHeader)
+    } else {
+        None
+    }
+}
+
+/// The arrow or block of the command at this index in the command strip, `row` is the procedure
+/// it belongs to or `None` for the main commands
+#[derive(Component)]
+struct CommandArrow {
+    row: Option<Procedure>,
+    index: usize,
+}
+
+/// Marks where the next command is entered in the command strip
+#[derive(Component)]
+struct CommandCursor;
+
+/// Spawns the rows of the command strip again whenever the entered commands or the cursor change
+fn sync_command_strip(
+    mut commands: Commands,
+    query: Query<&InputCommands, Changed<InputCommands>>,
+    ui: Query<Entity, With<CommandsContainerMarker>>,
+    procedure_ui: Query<(Entity, &ProcedureContainerMarker)>,
+    arrow_image: Res<ArrowImage>,
+    font: Res<FontHandle>,
+    state: Res<CurrentState<HarvestorState>>,
+) {
+    if state.0 != HarvestorState::AcceptingCommands {
+        return;
+    }
+    let input_commands = match query.iter().next() {
+        Some(input_commands) => input_commands,
+        None => return,
+    };
+
+    let rows = procedure_ui
+        .iter()
+        .map(|(e, marker)| (e, Some(marker.0)))
+        .chain(ui.iter().map(|e| (e, None)));
+    for (ui_entity, row) in rows {
+        commands.entity(ui_entity).despawn_descendants();
+        let queue = input_commands.queue(row);
+        let editing = input_commands.editing == row;
+
+        for (index, command) in queue.commands.iter().enumerate() {
+            if editing && queue.group_start == Some(index) {
+                spawn_group_start(&font, &mut commands, ui_entity);
+            }
+            if editing && index == queue.cursor {
+                spawn_command_cursor(&mut commands, ui_entity);
+            }
+            let arrow = spawn_command_ui(&arrow_image, &font, &mut commands, ui_entity, command);
+            commands.entity(arrow).insert(CommandArrow { row, index });
+        }
+        if editing && queue.group_start == Some(queue.commands.len()) {
+            spawn_group_start(&font, &mut commands, ui_entity);
+        }
+        if editing && queue.cursor == queue.commands.len() {
+            spawn_command_cursor(&mut commands, ui_entity);
+        }
+    }
+}
+
+fn spawn_command_cursor(commands: &mut Commands, ui_entity: Entity) {
+    commands.entity(ui_entity).with_children(|p| {
+        p.spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(4.0), Val::Px(50.0)),
+                margin: UiRect {
+                    left: Val::Px(0.0),
+                    right: Val::Px(8.0),
+                    top: Val::Px(0.0),
+                    bottom: Val::Px(0.0),
+                },
+                ..default()
+            },
+            color: Color::WHITE.into(),
+            ..default()
+        })
+        .insert(CommandCursor);
+    });
+}
+
+fn spawn_group_start(font: &Res<FontHandle>, commands: &mut Commands, ui_entity: Entity) {
+    commands.entity(ui_entity).with_children(|p| {
+        p.spawn_bundle(
+            TextBundle::from_section(
+                "[",
+                TextStyle {
+                    font: font.handle.clone(),
+                    font_size: 48.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                margin: UiRect {
+                    left: Val::Px(0.0),
+                    right: Val::Px(8.0),
+                    top: Val::Px(0.0),
+                    bottom: Val::Px(0.0),
+                },
+                ..default()
+            }),
+        );
+    });
+}
+
+const BLOCK_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+
+/// What a block in the command strip shows, in order
+enum BlockPart<'a> {
+    Label(String),
+    Commands(&'a [Command]),
+}
+
+/// Spawns an arrow, the name of a called procedure, or a block holding the arrows of its commands
+/// and what the block does with them
+fn spawn_command_ui(
     arrow_image: &Res<ArrowImage>,
+    font: &Res<FontHandle>,
     commands: &mut Commands,
     ui_entity: Entity,
-    command: &HarvestorCommands,
+    command: &Command,
+) -> Entity {
+    let parts = match command {
+        Command::Move(direction) => {
+            return spawn_image_command_ui(commands, ui_entity, move_icon(arrow_image, direction))
+        }
+        Command::Steer(steering) => {
+            return spawn_image_command_ui(
+                commands,
+                ui_entity,
+                steering_icon(arrow_image, steering),
+            )
+        }
+        Command::RaiseHeader => {
+            return spawn_image_command_ui(commands, ui_entity, (arrow_image.header.clone(), 0.0))
+        }
+        Command::LowerHeader => {
+            return spawn_image_command_ui(commands, ui_entity, (arrow_image.header.clone(), 180.0))
+        }
+        Command::Call(procedure) => return spawn_call_ui(font, commands, ui_entity, *procedure),
+        Command::Repeat(times, body) => vec![
+            BlockPart::Commands(body),
+            BlockPart::Label(format!("x{}", times)),
+        ],
+        Command::If(condition, then, otherwise) if otherwise.is_empty() => vec![
+            BlockPart::Label(format!("if {}", condition)),
+            BlockPart::Commands(then),
+        ],
+        Command::If(condition, then, otherwise) => vec![
+            BlockPart::Label(format!("if {}", condition)),
+            BlockPart::Commands(then),
+            BlockPart::Label("else".to_string()),
+            BlockPart::Commands(otherwise),
+        ],
+        Command::While(condition, body) => vec![
+            BlockPart::Label(format!("while {}", condition)),
+            BlockPart::Commands(body),
+        ],
+    };
+
+    let mut block = None;
+    commands.entity(ui_entity).with_children(|p| {
+        let entity = p.spawn_bundle(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                padding: UiRect::all(Val::Px(4.0)),
+                margin: UiRect {
+                    left: Val::Px(0.0),
+                    right: Val::Px(8.0),
+                    top: Val::Px(0.0),
+                    bottom: Val::Px(0.0),
+                },
+                ..default()
+            },
+            color: BLOCK_COLOR.into(),
+            ..default()
+        });
+        block = Some(entity.id());
+    });
+    let block = block.expect("the block was just spawned");
+
+    for part in parts {
+        match part {
+            BlockPart::Commands(body) => {
+                for command in body {
+                    spawn_command_ui(arrow_image, font, commands, block, command);
+                }
+            }
+            BlockPart::Label(label) => {
+                commands.entity(block).with_children(|p| {
+                    p.spawn_bundle(
+                        TextBundle::from_section(
+                            label,
+                            TextStyle {
+                                font: font.handle.clone(),
+                                font_size: 32.0,
+                                color: Color::WHITE,
+                            },
+                        )
+                        .with_style(Style {
+                            margin: UiRect::all(Val::Px(4.0)),
+                            ..default()
+                        }),
+                    );
+                });
+            }
+        }
+    }
+
+    block
+}
+
+fn spawn_call_ui(
+    font: &Res<FontHandle>,
+    commands: &mut Commands,
+    ui_entity: Entity,
+    procedure: Procedure,
+) -> Entity {
+    let mut call = None;
+    commands.entity(ui_entity).with_children(|p| {
+        let entity = p
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Px(50.0), Val::Px(50.0)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    margin: UiRect {
+                        left: Val::Px(0.0),
+                        right: Val::Px(8.0),
+                        top: Val::Px(0.0),
+                        bottom: Val::Px(0.0),
+                    },
+                    ..default()
+                },
+                color: BLOCK_COLOR.into(),
+                ..default()
+            })
+            .with_children(|p| {
+                p.spawn_bundle(TextBundle::from_section(
+                    procedure.name(),
+                    TextStyle {
+                        font: font.handle.clone(),
+                        font_size: 32.0,
+                        color: Color::WHITE,
+                    },
+                ));
+            })
+            .id();
+        call = Some(entity);
+    });
+
+    call.expect("the call was just spawned")
+}
+
+fn hide_command_cursor(mut commands: Commands, cursor_q: Query<Entity, With<CommandCursor>>) {
+    cursor_q
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+}
+
+/// A square the entered commands will harvest, or the arrow where they leave the harvestor
+#[derive(Component)]
+struct PathPreview;
+
+const PATH_PREVIEW_COLOR: Color = Color::rgba(0.9, 0.9, 0.6, 0.4);
+const PATH_PREVIEW_END_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.8);
+const PATH_PREVIEW_CRASH_COLOR: Color = Color::rgba(0.9, 0.1, 0.1, 0.8);
+
+struct PathPreviewAssets {
+    square_mesh: Handle<Mesh>,
+    arrow_mesh: Handle<Mesh>,
+    square: Handle<StandardMaterial>,
+    end: Handle<StandardMaterial>,
+    crash: Handle<StandardMaterial>,
+}
+
+impl FromWorld for PathPreviewAssets {
+    fn from_world(world: &mut World) -> Self {
+        let arrow_image: Handle<Image> = world.resource::<AssetServer>().load("arrow.png");
+
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        let square_mesh = meshes.add(Mesh::from(shape::Box::new(
+            FIELD_SIZE * 0.8,
+            FIELD_SIZE * FIELD_THICKNESS,
+            FIELD_SIZE * 0.8,
+        )));
+        let arrow_mesh = meshes.add(Mesh::from(shape::Quad::new(Vec2::splat(FIELD_SIZE))));
+
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let mut translucent = |color: Color, texture: Option<Handle<Image>>| {
+            materials.add(StandardMaterial {
+                base_color: color,
+                base_color_texture: texture,
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            })
+        };
+
+        PathPreviewAssets {
+            square_mesh,
+            arrow_mesh,
+            square: translucent(PATH_PREVIEW_COLOR, None),
+            end: translucent(PATH_PREVIEW_END_COLOR, Some(arrow_image.clone())),
+            crash: translucent(PATH_PREVIEW_CRASH_COLOR, Some(arrow_image)),
+        }
+    }
+}
+
+/// Simulates the entered commands whenever they change, and marks every square they harvest
+/// and where the harvestor ends up on the canvas field
+fn preview_path(
+    mut commands: Commands,
+    query: Query<(&Harvestor, &InputCommands), Changed<InputCommands>>,
+    field_q: Query<&Field>,
+    preview_q: Query<Entity, With<PathPreview>>,
+    assets: Res<PathPreviewAssets>,
+) {
+    let (h, input_commands, field) = match (query.iter().next(), field_q.iter().next()) {
+        (Some((h, input_commands)), Some(field)) => (h, input_commands, field),
+        _ => return,
+    };
+    preview_q
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+    let program = input_commands.program();
+    if program.size() == 0 {
+        return;
+    }
+
+    let size = field.size();
+    let trace = run(
+        h.position,
+        h.direction.clone(),
+        &program,
+        &h.field_spec(field),
+    );
+    // above the squares and the marker of the harvestor position
+    let lift = Vec3::Y * FIELD_SIZE * FIELD_THICKNESS * 2.0;
+    for (x, y) in trace.mowed.keys() {
+        let position = IVec2::new(*x, *y);
+        if !is_in_bounds(position, size) {
+            continue;
+        }
+
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: assets.square_mesh.clone(),
+                material: assets.square.clone(),
+                transform: Transform::from_translation(
+                    canvas_square_translation(size, position) + lift,
+                ),
+                ..default()
+            })
+            .insert(PathPreview);
+    }
+
+    // the arrow image points down, so its bottom goes where the harvestor faces and it lies
+    // flat facing up
+    let facing = command_to_direction(&trace.facing);
+    let rotation = Quat::from_mat3(&Mat3::from_cols(Vec3::Y.cross(facing), -facing, Vec3::Y));
+    let material = match trace.crash {
+        Some(_) => assets.crash.clone(),
+        None => assets.end.clone(),
+    };
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: assets.arrow_mesh.clone(),
+            material,
+            transform: Transform::from_translation(
+                canvas_square_translation(size, trace.position()) + lift * 2.0,
+            )
+            .with_rotation(rotation),
+            ..default()
+        })
+        .insert(PathPreview);
+}
+
+fn hide_path_preview(mut commands: Commands, preview_q: Query<Entity, With<PathPreview>>) {
+    preview_q
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+}
+
+/// Colors the arrow of the main command at `index`
+fn highlight_command(
+    commands: &mut Commands,
+    arrows_q: &Query<(Entity, &CommandArrow)>,
+    index: usize,
+    color: Color,
+) -> Option<Entity> {
+    let (arrow, _) = arrows_q
+        .iter()
+        .find(|(_, arrow)| arrow.row.is_none() && arrow.index == index)?;
+    // takes over from the running highlight, so it isn't undone when the run moves on
+    commands
+        .entity(arrow)
+        .insert(UiColor(color))
+        .remove::<RunningHighlight>();
+
+    Some(arrow)
+}
+
+const RUNNING_COLOR: Color = Color::rgba(0.3, 0.9, 0.4, 0.8);
+
+/// Marks the command that is running, with the color it had before
+#[derive(Component)]
+struct RunningHighlight(Color);
+
+/// Colors the command in the main row that the harvestor is running, or runs next while paused
+fn highlight_running_command(
+    mut commands: Commands,
+    harvestor_q: Query<(&Harvestor, &InputCommands)>,
+    arrows_q: Query<(Entity, &CommandArrow, &UiColor, Option<&RunningHighlight>)>,
+    state: Res<CurrentState<HarvestorState>>,
+) {
+    let running = harvestor_q.iter().next().and_then(|(h, input_commands)| {
+        match (&h.step, input_commands.steps.front()) {
+            (Some(step), _) => Some(step),
+            (None, next) if state.0 == HarvestorState::Running => next,
+            _ => None,
+        }
+    });
+    if let Some(TraceStep::Crash { .. }) = running {
+        // highlighted as crashed instead
+        return;
+    }
+    let running = running.map(TraceStep::command_index);
+
+    for (e, arrow, color, highlight) in arrows_q.iter() {
+        let is_running = arrow.row.is_none() && Some(arrow.index) == running;
+        match (is_running, highlight) {
+            (true, None) => {
+                commands
+                    .entity(e)
+                    .insert(RunningHighlight(color.0))
+                    .insert(UiColor(RUNNING_COLOR));
+            }
+            (false, Some(RunningHighlight(color))) => {
+                commands
+                    .entity(e)
+                    .insert(UiColor(*color))
+                    .remove::<RunningHighlight>();
+            }
+            _ => {}
+        }
+    }
+}
+
+const CRASHED_COMMAND_COLOR: Color = Color::rgb(0.9, 0.1, 0.1);
+
+/// Colors the arrow of the command that made the harvestor crash
+fn highlight_crashed_command(
+    mut commands: Commands,
+    mut ev_crashed: EventReader<HarvestorCrashedEvent>,
+    arrows_q: Query<(Entity, &CommandArrow)>,
+) {
+    for HarvestorCrashedEvent(crash) in ev_crashed.iter() {
+        highlight_command(
+            &mut commands,
+            &arrows_q,
+            crash.command_index,
+            CRASHED_COMMAND_COLOR,
+        );
+    }
+}
+
+const HINT_COLOR: Color = Color::rgba(1.0, 0.85, 0.2, 0.6);
+
+/// The arrow of a hinted command that hasn't been entered yet, it goes away with the next command
+#[derive(Component)]
+struct HintArrow;
+
+/// A command a hint pointed out as wrong, the highlight goes away with the next change to the
+/// commands or once they run
+#[derive(Component)]
+struct HintHighlight;
+
+fn clear_hint(
+    mut commands: Commands,
+    hint_arrow_q: Query<Entity, With<HintArrow>>,
+    hint_highlight_q: Query<Entity, With<HintHighlight>>,
+) {
+    hint_arrow_q
+        .iter()
+        .for_each(|e| commands.entity(e).despawn_recursive());
+    hint_highlight_q.iter().for_each(|e| {
+        commands
+            .entity(e)
+            .insert(UiColor::default())
+            .remove::<HintHighlight>();
+    });
+}
+
+/// Press `H` while entering commands to get the next command towards a perfect harvest, or the
+/// command that went wrong
+#[allow(clippy::too_many_arguments)]
+fn show_hint(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    query: Query<&InputCommands>,
+    ui: Query<Entity, With<CommandsContainerMarker>>,
+    arrows_q: Query<(Entity, &CommandArrow)>,
+    help_ui_container_q: Query<Entity, With<HelpTextContainer>>,
+    arrow_image: Res<ArrowImage>,
+    font: Res<FontHandle>,
+    active_level: Res<ActiveLevel>,
+    levels: Res<Assets<Level>>,
+    mut hint_count: ResMut<HintCount>,
+    program_text: Res<ProgramText>,
+) {
+    if !keys.just_released(KeyCode::H) || program_text.typing.is_some() {
+        return;
+    }
+    let (input_commands, level) = match (query.iter().next(), levels.get(&active_level.handle)) {
+        (Some(input_commands), Some(level)) => (input_commands, level),
+        _ => return,
+    };
+    let program = input_commands.program();
+    if hint_count.hinted_at.as_ref() == Some(&program) {
+        return;
+    }
+
+    let ui_entity = ui.single();
+    let puzzle = &level.puzzle;
+    let (origins, expanded): (Vec<_>, Vec<_>) = expand(
+        puzzle.start,
+        puzzle.facing.clone(),
+        &program,
+        &puzzle.field_spec(),
+    )
+    .into_iter()
+    .unzip();
+    let trace = run(
+        puzzle.start,
+        puzzle.facing.clone(),
+        &program,
+        &puzzle.field_spec(),
+    );
+    // the solver harvests every square it drives onto, so it can't follow a raised header
+    let header_used = trace.header_toggles() > 0;
+    let next = if header_used {
+        None
+    } else {
+        hint(&level.puzzle, &expanded)
+    };
+    let (help_text, used) = match next {
+        Some(Hint::Next(command)) => {
+            let icon = match puzzle.controls {
+                Controls::Absolute => move_icon(&arrow_image, &command),
+                Controls::Relative { reverse } => {
+                    steering_icon(&arrow_image, &steer(trace.facing, &[command], reverse)[0])
+                }
+            };
+            let arrow = spawn_image_command_ui(&mut commands, ui_entity, icon);
+            commands
+                .entity(arrow)
+                .insert(HintArrow)
+                .insert(UiColor(HINT_COLOR));
+            ("Hint: the highlighted command comes next", true)
+        }
+        Some(Hint::Wrong(command_index)) => {
+            if let Some(arrow) =
+                highlight_command(&mut commands, &arrows_q, origins[command_index], HINT_COLOR)
+            {
+                commands.entity(arrow).insert(HintHighlight);
+            }
+            ("Hint: the highlighted command spoils the harvest", true)
+        }
+        Some(Hint::Run) => (
+            "These commands harvest the field, press Enter to execute them",
+            false,
+        ),
+        None if header_used || puzzle.header_toggles.is_some() => (
+            "No hint, hints don't cover raising and lowering the header",
+            false,
+        ),
+        None => ("No hint for this board", false),
+    };
+    if used {
+        hint_count.used += 1;
+        hint_count.hinted_at = Some(program);
+    }
+
+    let e = help_ui_container_q.single();
+    update_help_text(&font, &mut commands, e, help_text);
+}
+
+/// `T` opens a text field with the entered commands written like `U3 R (U R)x4`, `Enter`
+/// replaces them with the typed program or tells where it can't be read
+#[allow(clippy::too_many_arguments)]
+fn type_program(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut received_characters: EventReader<ReceivedCharacter>,
+    mut program_text: ResMut<ProgramText>,
+    mut query: Query<&mut InputCommands>,
+    help_ui_container_q: Query<Entity, With<HelpTextContainer>>,
+    font: Res<FontHandle>,
+    active_level: Res<ActiveLevel>,
+    levels: Res<Assets<Level>>,
 ) {
+    let mut typed = received_characters
+        .iter()
+        .map(|received| received.char)
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+    let opened = program_text.typing.is_none();
+    if opened {
+        if !keys.just_released(KeyCode::T) {
+            return;
+        }
+        program_text.typing = Some(
+            query
+                .iter()
+                .next()
+                .map_or_else(String::new, |ic| ic.program().to_string()),
+        );
+        // the `t` that opened it
+        typed.clear();
+    }
+    let back = keys.just_pressed(KeyCode::Back);
+    let entered = keys.just_released(KeyCode::Return);
+    if !opened && typed.is_empty() && !back && !entered {
+        return;
+    }
+
+    let text = program_text.typing.get_or_insert_with(String::new);
+    text.push_str(&typed);
+    if back {
+        text.pop();
+    }
+    let puzzle = levels.get(&active_level.handle).map(|level| &level.puzzle);
+    let help_text = match text.parse::<Program>() {
+        Ok(program) if entered => match puzzle.and_then(|p| p.excess_header_toggles(&program)) {
+            Some((used, allowed)) => format!(
+                "Program: {}_  (this board allows {} header toggles, it makes {})",
+                text, allowed, used
+            ),
+            _ => {
+                query
+                    .iter_mut()
+                    .for_each(|mut ic| ic.set_program(program.clone()));
+                program_text.typing = None;
+                "Press Enter to execute commands, Backspace to remove one".to_string()
+            }
+        },
+        Err(err) if entered => format!("Program: {}_  ({})", text, err),
+        _ => format!("Program: {}_  (Enter to use it)", text),
+    };
+
+    let e = help_ui_container_q.single();
+    update_help_text(&font, &mut commands, e, &help_text);
+}
+
+/// The arrow image for a move and how far it's rotated, in degrees
+fn move_icon(arrow_image: &ArrowImage, direction: &HarvestorCommands) -> (Handle<Image>, f32) {
+    let degrees = match direction {
+        HarvestorCommands::Up => 180.0,
+        HarvestorCommands::Down => 0.0,
+        HarvestorCommands::Left => 270.0,
+        HarvestorCommands::Right => 90.0,
+    };
+
+    (arrow_image.handle.clone(), degrees)
+}
+
+/// Forward and reverse point up and down like moves, turns have their own bent arrows
+fn steering_icon(arrow_image: &ArrowImage, steering: &Steering) -> (Handle<Image>, f32) {
+    match steering {
+        Steering::Forward => (arrow_image.handle.clone(), 180.0),
+        Steering::Reverse => (arrow_image.handle.clone(), 0.0),
+        Steering::TurnLeft => (arrow_image.turn_left.clone(), 0.0),
+        Steering::TurnRight => (arrow_image.turn_right.clone(), 0.0),
+    }
+}
+
+fn spawn_image_command_ui(
+    commands: &mut Commands,
+    ui_entity: Entity,
+    (image, degrees): (Handle<Image>, f32),
+) -> Entity {
+    let mut arrow = None;
     let mut command_ui_parent = commands.entity(ui_entity);
     command_ui_parent.with_children(|p| {
-        let degrees = match command {
-            HarvestorCommands::Up => 180.0,
-            HarvestorCommands::Down => 0.0,
-            HarvestorCommands::Left => 270.0,
-            HarvestorCommands::Right => 90.0,
-        };
-
         let radians = PI / 180.0 * degrees;
-        p.spawn_bundle(ImageBundle {
+        let entity = p.spawn_bundle(ImageBundle {
             transform: Transform::default().with_rotation(Quat::from_axis_angle(Vec3::Z, radians)),
             style: Style {
                 size: Size::new(Val::Px(50.0), Val::Px(50.0)),
@@ -355,8 +1862,128 @@ fn spawn_image_command_ui(
                 },
                 ..default()
             },
-            image: arrow_image.handle.clone().into(),
+            image: image.into(),
             ..default()
         });
+        arrow = Some(entity.id());
     });
+
+    arrow.expect("the arrow was just spawned")
+}
+
+#[test]
+fn edit_commands_at_the_cursor() {
+    let [up, left, right] = [
+        HarvestorCommands::Up,
+        HarvestorCommands::Left,
+        HarvestorCommands::Right,
+    ]
+    .map(Command::Move);
+    let mut input_commands = CommandQueue::default();
+    input_commands.insert(up.clone());
+    input_commands.insert(right.clone());
+    input_commands.move_cursor(1);
+    input_commands.insert(left.clone());
+    assert_eq!(
+        input_commands.commands,
+        vec![up.clone(), left, right.clone()]
+    );
+    assert_eq!(input_commands.cursor, 2);
+
+    input_commands.remove_before_cursor();
+    assert_eq!(input_commands.commands, vec![up.clone(), right]);
+    input_commands.remove_after_cursor();
+    assert_eq!(input_commands.commands, vec![up.clone()]);
+    input_commands.remove_after_cursor();
+    assert_eq!(input_commands.commands, vec![up]);
+
+    input_commands.move_cursor(usize::MAX);
+    assert_eq!(input_commands.cursor, 1);
+    input_commands.remove_before_cursor();
+    input_commands.remove_before_cursor();
+    assert!(input_commands.commands.is_empty());
+    assert_eq!(input_commands.cursor, 0);
+}
+
+#[test]
+fn digits_repeat_commands() {
+    let [up, right] = [HarvestorCommands::Up, HarvestorCommands::Right].map(Command::Move);
+    let mut input_commands = CommandQueue::default();
+    input_commands.insert(up.clone());
+    input_commands.repeat(3);
+    assert_eq!(
+        input_commands.commands,
+        vec![Command::Repeat(3, vec![up.clone()])]
+    );
+    input_commands.repeat(4);
+    assert_eq!(
+        input_commands.commands,
+        vec![Command::Repeat(4, vec![up.clone()])]
+    );
+
+    input_commands.start_group();
+    input_commands.insert(right.clone());
+    input_commands.insert(up.clone());
+    input_commands.repeat(2);
+    assert_eq!(
+        input_commands.commands,
+        vec![
+            Command::Repeat(4, vec![up.clone()]),
+            Command::Repeat(2, vec![right.clone(), up.clone()]),
+        ]
+    );
+    assert_eq!(input_commands.cursor, 2);
+
+    input_commands.repeat(1);
+    assert_eq!(
+        input_commands.commands,
+        vec![Command::Repeat(4, vec![up.clone()]), right, up]
+    );
+    assert_eq!(input_commands.cursor, 3);
+}
+
+#[test]
+fn if_blocks_take_an_else_branch() {
+    let [up, right] = [HarvestorCommands::Up, HarvestorCommands::Right].map(Command::Move);
+    let mut input_commands = CommandQueue::default();
+    input_commands.insert(right.clone());
+    // nothing to attach it to
+    input_commands.otherwise();
+    assert_eq!(input_commands.commands, vec![right.clone()]);
+    assert_eq!(input_commands.cursor, 1);
+
+    input_commands.remove_before_cursor();
+    input_commands.insert(up.clone());
+    input_commands.wrap(|body| Command::If(Condition::AheadUnmowed, body, vec![]));
+    input_commands.insert(right.clone());
+    input_commands.otherwise();
+    input_commands.negate();
+    assert_eq!(
+        input_commands.commands,
+        vec![Command::If(
+            Condition::AheadUnmowed.negated(),
+            vec![up],
+            vec![right]
+        )]
+    );
+    assert_eq!(input_commands.cursor, 1);
+}
+
+#[test]
+fn playback_speed_scales_the_step_duration() {
+    let mut playback_speed = PlaybackSpeed::default();
+    assert_eq!(playback_speed.to_string(), "1x");
+    playback_speed.slower();
+    playback_speed.slower();
+    assert_eq!(
+        playback_speed.step_duration(),
+        Some(Duration::from_secs_f32(HARVESTOR_MOVEMENT_TIME * 2.0))
+    );
+
+    (0..4).for_each(|_| playback_speed.faster());
+    assert_eq!(playback_speed.to_string(), "8x");
+    playback_speed.faster();
+    playback_speed.faster();
+    assert_eq!(playback_speed.to_string(), "instant");
+    assert_eq!(playback_speed.step_duration(), None);
 }
@@ -0,0 +1,545 @@
+use crate::field::Obstacle;
+use crate::harvestor::HarvestorCommands;
+use crate::program::Program;
+use crate::puzzle::{
+    arg_value, Controls, Puzzle, PuzzleError, HARVESTOR_START, HARVESTOR_START_FACING,
+};
+use crate::simulation::HeaderWidth;
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::{BoxedFuture, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Level>()
+            .init_resource::<LevelErrors>()
+            .init_asset_loader::<LevelLoader>()
+            .init_resource::<ActiveLevel>();
+    }
+}
+
+/// A board read from a `.level` file:
+///
+/// ```text
+/// name: Around the corner
+/// start: 0 -1 left
+/// solution: U2 R3
+/// ....T
+/// ####.
+/// #.R..
+/// ```
+///
+/// Metadata lines are `key: value` pairs, `start`, `solution`, `controls`, `header toggles` and
+/// `header width` are optional. `controls: relative` steers the harvestor from its own point of
+/// view, add `reverse` to let it back up. `header toggles: 2` allows raising or lowering the
+/// header twice while a program runs and `header width: 3` harvests three squares side by side.
+/// The solution is a program like the ones entered in game, see [`Program`]'s text form.
+/// The grid is the target field as seen in game, the bottom row being the one closest to the
+/// harvestor start. `#` is wheat the harvestor has to cut, `.` has to be left standing and
+/// `R`, `T` and `B` are a rock, tree or barn in the way.
+#[derive(TypeUuid, Debug, Clone)]
+#[uuid = "5d1c7b6e-3f0a-4c5e-9b8e-2a4f6d9c1e73"]
+pub struct Level {
+    pub puzzle: Puzzle,
+    /// Any other metadata in the file, such as `name` or `author`
+    pub metadata: HashMap<String, String>,
+    /// [`Puzzle::par`], worked out once as the solver takes a while on big fields
+    pub par: usize,
+}
+
+impl Level {
+    pub fn new(puzzle: Puzzle, metadata: HashMap<String, String>) -> Self {
+        let par = puzzle.par();
+
+        Level {
+            puzzle,
+            metadata,
+            par,
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.metadata.get("name").map(|name| name.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelError {
+    /// Line in the level file, starting at 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+fn level_error(line: usize, message: impl Into<String>) -> LevelError {
+    LevelError {
+        line,
+        message: message.into(),
+    }
+}
+
+pub fn parse_direction(direction: &str) -> Option<HarvestorCommands> {
+    match direction.to_lowercase().as_str() {
+        "up" | "u" => Some(HarvestorCommands::Up),
+        "down" | "d" => Some(HarvestorCommands::Down),
+        "left" | "l" => Some(HarvestorCommands::Left),
+        "right" | "r" => Some(HarvestorCommands::Right),
+        _ => None,
+    }
+}
+
+fn direction_name(direction: &HarvestorCommands) -> &'static str {
+    match direction {
+        HarvestorCommands::Up => "up",
+        HarvestorCommands::Down => "down",
+        HarvestorCommands::Left => "left",
+        HarvestorCommands::Right => "right",
+    }
+}
+
+pub fn parse_level(text: &str) -> Result<Level, LevelError> {
+    let mut metadata = HashMap::new();
+    let mut start = HARVESTOR_START;
+    let mut facing = HARVESTOR_START_FACING;
+    let mut solution = Program::default();
+    let mut solution_line = 0;
+    let mut controls = Controls::Absolute;
+    let mut header_toggles = None;
+    let mut header_width = HeaderWidth::One;
+    let mut rows: Vec<(usize, &str)> = vec![];
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => {
+                rows.push((line_number, line));
+                continue;
+            }
+        };
+        if !rows.is_empty() {
+            return Err(level_error(
+                line_number,
+                "metadata has to come before the field",
+            ));
+        }
+
+        match key {
+            "start" => {
+                let parts = value.split_whitespace().collect::<Vec<_>>();
+                let (x, y, direction) = match parts.as_slice() {
+                    [x, y, direction] => (x, y, direction),
+                    _ => return Err(level_error(line_number, "expected `start: x y direction`")),
+                };
+                start = IVec2::new(
+                    x.parse()
+                        .map_err(|_| level_error(line_number, "start x is not a number"))?,
+                    y.parse()
+                        .map_err(|_| level_error(line_number, "start y is not a number"))?,
+                );
+                facing = parse_direction(direction)
+                    .ok_or_else(|| level_error(line_number, "unknown start direction"))?;
+            }
+            "solution" => {
+                solution_line = line_number;
+                solution = value
+                    .parse()
+                    .map_err(|err| level_error(line_number, format!("solution at {}", err)))?;
+            }
+            "controls" => {
+                controls = match value.split_whitespace().collect::<Vec<_>>().as_slice() {
+                    ["absolute"] => Controls::Absolute,
+                    ["relative"] => Controls::Relative { reverse: false },
+                    ["relative", "reverse"] => Controls::Relative { reverse: true },
+                    _ => return Err(level_error(line_number, "unknown controls")),
+                };
+            }
+            "header toggles" => {
+                header_toggles = Some(
+                    value
+                        .parse()
+                        .map_err(|_| level_error(line_number, "header toggles is not a number"))?,
+                );
+            }
+            "header width" => {
+                header_width = value
+                    .parse()
+                    .ok()
+                    .and_then(HeaderWidth::from_squares)
+                    .ok_or_else(|| level_error(line_number, "header width is 1, 3 or 5"))?;
+            }
+            _ => {
+                metadata.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    let width = match rows.first() {
+        Some((_, row)) => row.chars().count(),
+        None => return Err(level_error(text.lines().count(), "level has no field")),
+    };
+
+    let mut target = HashMap::new();
+    let mut obstacles = HashMap::new();
+    for (y, (line_number, row)) in rows.iter().rev().enumerate() {
+        if row.chars().count() != width {
+            return Err(level_error(
+                *line_number,
+                "all rows of the field should be equally wide",
+            ));
+        }
+
+        for (x, square) in row.chars().enumerate() {
+            let obstacle = match square {
+                'R' => Some(Obstacle::Rock),
+                'T' => Some(Obstacle::Tree),
+                'B' => Some(Obstacle::Barn),
+                _ => None,
+            };
+            if let Some(obstacle) = obstacle {
+                obstacles.insert((x as i32, y as i32), obstacle);
+            }
+
+            let harvest = match square {
+                '#' => true,
+                '.' | 'R' | 'T' | 'B' => false,
+                _ => {
+                    return Err(level_error(
+                        *line_number,
+                        format!("unknown square '{}'", square),
+                    ))
+                }
+            };
+            target.insert((x as i32, y as i32), !harvest);
+        }
+    }
+
+    let size = UVec2::new(width as u32, rows.len() as u32);
+    let mut puzzle = Puzzle::new(
+        size,
+        start,
+        facing,
+        target,
+        obstacles,
+        solution,
+        header_width,
+    )
+    .and_then(|puzzle| puzzle.with_header_toggles(header_toggles))
+    .map_err(|err| {
+        let message = match err {
+            PuzzleError::OutOfBounds(index) => {
+                format!("solution drives off the field at command {}", index + 1)
+            }
+            PuzzleError::HitsObstacle(index) => {
+                format!("solution drives into an obstacle at command {}", index + 1)
+            }
+            PuzzleError::TargetMismatch => {
+                "solution does not harvest the wheat in the field".to_string()
+            }
+            PuzzleError::TooManyHeaderToggles(used) => format!(
+                "solution raises or lowers the header {} times, more often than allowed",
+                used
+            ),
+        };
+        level_error(solution_line, message)
+    })?;
+    puzzle.controls = controls;
+
+    Ok(Level::new(puzzle, metadata))
+}
+
+/// Writes the level in the same format [`parse_level`] reads
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let puzzle = &self.puzzle;
+
+        let mut metadata = self.metadata.iter().collect::<Vec<_>>();
+        metadata.sort();
+        for (key, value) in metadata {
+            writeln!(f, "{}: {}", key, value)?;
+        }
+        writeln!(
+            f,
+            "start: {} {} {}",
+            puzzle.start.x,
+            puzzle.start.y,
+            direction_name(&puzzle.facing)
+        )?;
+        match puzzle.controls {
+            Controls::Absolute => {}
+            Controls::Relative { reverse: false } => writeln!(f, "controls: relative")?,
+            Controls::Relative { reverse: true } => writeln!(f, "controls: relative reverse")?,
+        }
+        if puzzle.header_width != HeaderWidth::One {
+            writeln!(f, "header width: {}", puzzle.header_width.squares())?;
+        }
+        if let Some(allowed) = puzzle.header_toggles {
+            writeln!(f, "header toggles: {}", allowed)?;
+        }
+        if puzzle.solution.size() > 0 {
+            writeln!(f, "solution: {}", puzzle.solution)?;
+        }
+        writeln!(f)?;
+
+        for y in (0..puzzle.size.y as i32).rev() {
+            let row = (0..puzzle.size.x as i32)
+                .map(
+                    |x| match (puzzle.obstacles.get(&(x, y)), puzzle.target.get(&(x, y))) {
+                        (Some(Obstacle::Rock), _) => 'R',
+                        (Some(Obstacle::Tree), _) => 'T',
+                        (Some(Obstacle::Barn), _) => 'B',
+                        (None, Some(true)) => '.',
+                        (None, _) => '#',
+                    },
+                )
+                .collect::<String>();
+            writeln!(f, "{}", row)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Why level files failed to load, by asset path. The asset server only logs the error, this keeps
+/// it around to show in game.
+#[derive(Clone, Default)]
+pub struct LevelErrors(Arc<Mutex<HashMap<PathBuf, LevelError>>>);
+
+impl LevelErrors {
+    pub fn get(&self, path: &Path) -> Option<LevelError> {
+        self.0.lock().unwrap().get(path).cloned()
+    }
+
+    fn insert(&self, path: &Path, error: LevelError) {
+        self.0.lock().unwrap().insert(path.to_path_buf(), error);
+    }
+}
+
+pub struct LevelLoader {
+    errors: LevelErrors,
+}
+
+impl FromWorld for LevelLoader {
+    fn from_world(world: &mut World) -> Self {
+        LevelLoader {
+            errors: world.resource::<LevelErrors>().clone(),
+        }
+    }
+}
+
+impl AssetLoader for LevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let level = parse_level(std::str::from_utf8(bytes)?).map_err(|err| {
+                self.errors.insert(load_context.path(), err.clone());
+                err
+            })?;
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level"]
+    }
+}
+
+/// The level that is being played.
+/// Start the game with `--level levels/corner.level` to play a level file,
+/// otherwise a level is generated from the [`crate::puzzle::PuzzleSeed`] for every board.
+/// A level file that doesn't load is replaced by a generated board.
+pub struct ActiveLevel {
+    pub handle: Handle<Level>,
+    pub random: bool,
+}
+
+impl FromWorld for ActiveLevel {
+    fn from_world(world: &mut World) -> Self {
+        match arg_value("--level") {
+            Some(path) => ActiveLevel {
+                handle: world.resource::<AssetServer>().load(path.as_str()),
+                random: false,
+            },
+            None => ActiveLevel {
+                handle: Handle::default(),
+                random: true,
+            },
+        }
+    }
+}
+
+#[test]
+fn parse_level_file() {
+    let level = parse_level(
+        "name: Around the corner
+        start: 0 -1 left
+        solution: UU (R)x3
+
+        ....T
+        ####.
+        #.R..",
+    )
+    .unwrap();
+
+    assert_eq!(level.name(), Some("Around the corner"));
+    assert_eq!(level.puzzle.size, UVec2::new(5, 3));
+    assert_eq!(level.puzzle.solution.size(), 4);
+    assert_eq!(level.puzzle.target.get(&(0, 0)), Some(&false));
+    assert_eq!(level.puzzle.target.get(&(1, 0)), Some(&true));
+    assert_eq!(level.puzzle.target.get(&(3, 1)), Some(&false));
+    assert_eq!(level.puzzle.target.get(&(0, 2)), Some(&true));
+    assert_eq!(level.puzzle.obstacles.get(&(2, 0)), Some(&Obstacle::Rock));
+    assert_eq!(level.puzzle.obstacles.get(&(4, 2)), Some(&Obstacle::Tree));
+    assert_eq!(level.puzzle.target.get(&(2, 0)), Some(&true));
+}
+
+#[test]
+fn level_errors_name_the_line() {
+    let err = parse_level("name: Broken\n##\n#x").unwrap_err();
+    assert_eq!(err.line, 3);
+
+    let err = parse_level("##\nname: Too late").unwrap_err();
+    assert_eq!(err.line, 2);
+
+    let err = parse_level("solution: UUUU\n#\n#").unwrap_err();
+    assert_eq!(err.line, 1);
+    assert_eq!(
+        err.message,
+        "solution drives off the field at command 3".to_string()
+    );
+
+    let err = parse_level("solution: U (U\n#\n#").unwrap_err();
+    assert_eq!(err.line, 1);
+    assert_eq!(
+        err.message,
+        "solution at column 5: expected ')'".to_string()
+    );
+
+    let err = parse_level("solution: UUU\n#\nR\n#").unwrap_err();
+    assert_eq!(
+        err.message,
+        "solution drives into an obstacle at command 2".to_string()
+    );
+}
+
+#[test]
+fn level_round_trip() {
+    let text = "author: Someone
+name: Around the corner
+start: 0 -1 left
+solution: U2 R3
+
+....T
+####.
+#.R..
+";
+    let level = parse_level(text).unwrap();
+
+    assert_eq!(level.to_string(), text);
+    assert_eq!(
+        parse_level(&level.to_string()).unwrap().puzzle,
+        level.puzzle
+    );
+}
+
+#[test]
+fn relative_controls_steer_the_solution() {
+    let text = "start: 0 -1 up
+controls: relative reverse
+solution: F2 > F3
+
+....T
+####.
+#.R..
+";
+    let level = parse_level(text).unwrap();
+
+    assert_eq!(level.puzzle.controls, Controls::Relative { reverse: true });
+    assert_eq!(level.puzzle.solution.size(), 6);
+    assert!(level.to_string().contains("controls: relative reverse\n"));
+
+    let err = parse_level("controls: sideways\n#").unwrap_err();
+    assert_eq!(err.message, "unknown controls".to_string());
+}
+
+#[test]
+fn raised_header_leaves_gaps() {
+    let text = "start: 0 -1 up
+header toggles: 2
+solution: U ^ U _ U
+
+#
+.
+#
+";
+    let level = parse_level(text).unwrap();
+
+    assert_eq!(level.puzzle.header_toggles, Some(2));
+    assert_eq!(level.to_string(), text);
+
+    let err = parse_level("header toggles: 1\nsolution: U ^ U _ U\n#\n.\n#").unwrap_err();
+    assert_eq!(err.line, 2);
+
+    // the loop raises and lowers the header twice each
+    let err = parse_level("header toggles: 3\nsolution: (U ^ U _)x2\n.\n#\n.\n#").unwrap_err();
+    assert_eq!(
+        err.message,
+        "solution raises or lowers the header 4 times, more often than allowed".to_string()
+    );
+}
+
+#[test]
+fn wide_header_harvests_side_by_side() {
+    let text = "start: 1 -1 up
+header width: 3
+solution: U2
+
+###
+###
+";
+    let level = parse_level(text).unwrap();
+
+    assert_eq!(level.puzzle.header_width, HeaderWidth::Three);
+    assert_eq!(level.par, 2);
+    assert_eq!(level.to_string(), text);
+
+    let err = parse_level("header width: 2\n#").unwrap_err();
+    assert_eq!(err.message, "header width is 1, 3 or 5".to_string());
+}
+
+#[test]
+fn header_levels_take_a_shorter_solution_as_par() {
+    // raising the header over the top of the gap saves going back down the left side
+    let field = "#.#\n#.#\n#.#\n#.#\n###";
+    let level = parse_level(&format!(
+        "header toggles: 2\nsolution: U U U U U ^ R _ R D D D D L\n\n{}",
+        field
+    ))
+    .unwrap();
+    assert_eq!(level.par, 14);
+
+    let level = parse_level(&format!("solution: U5 D4 R2 U4\n\n{}", field)).unwrap();
+    assert_eq!(level.par, 15);
+}
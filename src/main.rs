@@ -1,14 +1,25 @@
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
-use crate::field::FieldPlugin;
+use crate::campaign::CampaignPlugin;
+use crate::editor::EditorPlugin;
+use crate::field::{Field, FieldPlugin};
 use bevy::prelude::*;
 use bevy_inspector_egui::WorldInspectorPlugin;
 
 use crate::harvestor::HarvestorPlugin;
+use crate::level::LevelPlugin;
+use crate::puzzle::PuzzlePlugin;
 use crate::ui::UIPlugin;
 use crate::wheat::WheatPlugin;
 
+mod campaign;
+mod editor;
 mod field;
 mod harvestor;
+mod level;
+mod program;
+mod puzzle;
+mod simulation;
+mod solver;
 mod ui;
 mod wheat;
 mod wheat_mesh;
@@ -23,14 +34,18 @@ fn main() {
         .add_plugin(WheatPlugin)
         .add_plugin(HarvestorPlugin)
         .add_plugin(FieldPlugin)
+        .add_plugin(PuzzlePlugin)
+        .add_plugin(LevelPlugin)
+        .add_plugin(CampaignPlugin)
+        .add_plugin(EditorPlugin)
+        .add_system(fit_camera_to_field)
         // .add_plugin(LogDiagnosticsPlugin::default())
         // .add_plugin(FrameTimeDiagnosticsPlugin::default())
         .run();
 }
 fn setup(mut commands: Commands) {
     commands.spawn_bundle(Camera3dBundle {
-        transform: Transform::from_translation(Vec3::new(0.0001, 3.0, -4.0))
-            .looking_at(Vec3::new(0.0, 0.0, 0.0), Vec3::Y),
+        transform: camera_transform(UVec2::new(10, 10)),
         ..Default::default()
     });
 
@@ -42,3 +57,22 @@ fn setup(mut commands: Commands) {
             ..default()
         });
 }
+
+/// Frames both fields, moving the camera back for fields bigger than the default 10 by 10
+fn camera_transform(field_size: UVec2) -> Transform {
+    let zoom = field_size.x.max(field_size.y).max(1) as f32 / 10.0;
+
+    Transform::from_translation(Vec3::new(0.0001, 3.0, -4.0) * zoom)
+        .looking_at(Vec3::new(0.0, 0.0, 0.0), Vec3::Y)
+}
+
+fn fit_camera_to_field(
+    field_q: Query<&Field, Added<Field>>,
+    mut camera_q: Query<&mut Transform, With<Camera3d>>,
+) {
+    if let Some(field) = field_q.iter().next() {
+        camera_q.iter_mut().for_each(|mut tf| {
+            *tf = camera_transform(field.size());
+        });
+    }
+}
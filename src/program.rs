@@ -0,0 +1,977 @@
+use crate::harvestor::HarvestorCommands;
+use crate::puzzle::is_in_bounds;
+use crate::simulation::{FieldSpec, Simulation, Trace};
+use bevy::math::IVec2;
+use std::fmt;
+use std::str::FromStr;
+
+/// Calls nested deeper than this are skipped, so a procedure that calls itself still ends
+pub const MAX_CALL_DEPTH: usize = 8;
+/// A program stops after running this many commands and loop rounds, so a loop that never ends
+/// doesn't hang the game
+pub const MAX_RUN_LENGTH: usize = 10_000;
+
+/// A command of the program the player enters, blocks, calls and conditions are worked out while
+/// it runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Move(HarvestorCommands),
+    /// Drives or turns relative to where the harvestor faces
+    Steer(Steering),
+    /// Lifts the header so the harvestor drives without harvesting
+    RaiseHeader,
+    LowerHeader,
+    /// Runs the commands in the block this many times
+    Repeat(u32, Vec<Command>),
+    /// Runs the commands of a procedure
+    Call(Procedure),
+    /// Runs the first block when the condition holds, the second one otherwise
+    If(Condition, Vec<Command>, Vec<Command>),
+    /// Runs the block for as long as the condition holds
+    While(Condition, Vec<Command>),
+}
+
+impl Command {
+    /// How much this command counts towards the par, a block costs one more than its contents
+    pub fn size(&self) -> usize {
+        match self {
+            Command::Move(_)
+            | Command::Steer(_)
+            | Command::RaiseHeader
+            | Command::LowerHeader
+            | Command::Call(_) => 1,
+            Command::Repeat(_, body) | Command::While(_, body) => 1 + commands_size(body),
+            Command::If(_, then, otherwise) => 1 + commands_size(then) + commands_size(otherwise),
+        }
+    }
+}
+
+fn commands_size(commands: &[Command]) -> usize {
+    commands.iter().map(Command::size).sum()
+}
+
+/// Commands for levels played with relative controls, see [`crate::puzzle::Controls`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Steering {
+    /// Drives one square ahead
+    Forward,
+    /// Turns a quarter to the left without moving
+    TurnLeft,
+    TurnRight,
+    /// Backs up one square, still facing the same way
+    Reverse,
+}
+
+impl Steering {
+    fn letter(&self) -> char {
+        match self {
+            Steering::Forward => 'F',
+            Steering::TurnLeft => '<',
+            Steering::TurnRight => '>',
+            Steering::Reverse => 'B',
+        }
+    }
+}
+
+/// The steering that drives the harvestor one square in `direction` when it faces `facing`.
+/// Without `reverse` it turns around to drive backwards.
+pub fn steering_towards(
+    facing: &HarvestorCommands,
+    direction: &HarvestorCommands,
+    reverse: bool,
+) -> Vec<Steering> {
+    if direction == facing {
+        vec![Steering::Forward]
+    } else if *direction == facing.turned_left() {
+        vec![Steering::TurnLeft, Steering::Forward]
+    } else if *direction == facing.turned_right() {
+        vec![Steering::TurnRight, Steering::Forward]
+    } else if reverse {
+        vec![Steering::Reverse]
+    } else {
+        vec![Steering::TurnLeft, Steering::TurnLeft, Steering::Forward]
+    }
+}
+
+/// Steers along a route of moves, starting out facing `facing`
+pub fn steer(
+    facing: HarvestorCommands,
+    moves: &[HarvestorCommands],
+    reverse: bool,
+) -> Vec<Steering> {
+    let mut facing = facing;
+    let mut steering = vec![];
+    for direction in moves {
+        let towards = steering_towards(&facing, direction, reverse);
+        if towards != [Steering::Reverse] {
+            facing = direction.clone();
+        }
+        steering.extend(towards);
+    }
+
+    steering
+}
+
+/// What the harvestor senses about the square in front of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// The square ahead is on the field, free of obstacles and not harvested yet
+    AheadUnmowed,
+    /// The square ahead is off the field
+    AtEdge,
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    pub fn holds(&self, simulation: &Simulation) -> bool {
+        let ahead = simulation.ahead();
+        let field = simulation.field();
+        let coord = (ahead.x, ahead.y);
+
+        match self {
+            Condition::AheadUnmowed => {
+                is_in_bounds(ahead, field.size)
+                    && !field.obstacles.contains_key(&coord)
+                    && !simulation.trace().mowed.get(&coord).unwrap_or(&false)
+            }
+            Condition::AtEdge => !is_in_bounds(ahead, field.size),
+            Condition::Not(condition) => !condition.holds(simulation),
+        }
+    }
+
+    pub fn negated(self) -> Condition {
+        match self {
+            Condition::Not(condition) => *condition,
+            condition => Condition::Not(Box::new(condition)),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::AheadUnmowed => write!(f, "unmowed ahead"),
+            Condition::AtEdge => write!(f, "at edge"),
+            Condition::Not(condition) => write!(f, "not {}", condition),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Procedure {
+    P1,
+    P2,
+}
+
+impl Procedure {
+    pub const ALL: [Procedure; 2] = [Procedure::P1, Procedure::P2];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Procedure::P1 => "P1",
+            Procedure::P2 => "P2",
+        }
+    }
+}
+
+/// The main program and the procedures it can call
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    pub main: Vec<Command>,
+    pub procedures: [Vec<Command>; 2],
+}
+
+impl Program {
+    /// A program that makes exactly these moves
+    pub fn from_moves(moves: &[HarvestorCommands]) -> Program {
+        Program {
+            main: moves.iter().cloned().map(Command::Move).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn procedure(&self, procedure: Procedure) -> &[Command] {
+        &self.procedures[procedure as usize]
+    }
+
+    /// How much the program counts towards the par, procedures count once no matter how often
+    /// they're called
+    pub fn size(&self) -> usize {
+        commands_size(&self.main)
+            + self
+                .procedures
+                .iter()
+                .map(|p| commands_size(p))
+                .sum::<usize>()
+    }
+}
+
+/// Where and why program text couldn't be read
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Character in the text, starting at 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Reads programs written like `U3 R P1; P1: (U R)x4 if unmowed ahead (U) else (L)`. Relative
+/// controls are written `F` for forward, `<` and `>` to turn and `B` to back up, `^` raises the
+/// header and `_` lowers it.
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn error_at(&self, position: usize, message: impl Into<String>) -> ParseError {
+        ParseError {
+            column: position + 1,
+            message: message.into(),
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        self.error_at(self.position, message)
+    }
+
+    /// The next character that isn't whitespace
+    fn peek(&mut self) -> Option<char> {
+        while self
+            .chars
+            .get(self.position)
+            .map_or(false, |c| c.is_whitespace())
+        {
+            self.position += 1;
+        }
+        self.chars.get(self.position).copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        if self.peek() != Some(expected) {
+            return Err(self.error(format!("expected '{}'", expected)));
+        }
+        self.position += 1;
+
+        Ok(())
+    }
+
+    /// Reads the digits right at the current position
+    fn number(&mut self) -> Result<u32, ParseError> {
+        let start = self.position;
+        while self
+            .chars
+            .get(self.position)
+            .map_or(false, |c| c.is_ascii_digit())
+        {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err(self.error("expected a number"));
+        }
+
+        self.chars[start..self.position]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .map_err(|_| self.error_at(start, "number is too large"))
+    }
+
+    /// A number of times to run something, it has to be run at least once
+    fn count(&mut self) -> Result<u32, ParseError> {
+        let start = self.position;
+        match self.number()? {
+            0 => Err(self.error_at(start, "count has to be at least 1")),
+            count if count as usize > MAX_RUN_LENGTH => {
+                Err(self.error_at(start, "count is too large"))
+            }
+            count => Ok(count),
+        }
+    }
+
+    fn word(&mut self) -> String {
+        self.peek();
+        let start = self.position;
+        while self
+            .chars
+            .get(self.position)
+            .map_or(false, |c| c.is_ascii_lowercase())
+        {
+            self.position += 1;
+        }
+
+        self.chars[start..self.position].iter().collect()
+    }
+
+    fn expect_word(&mut self, expected: &str) -> Result<(), ParseError> {
+        let start = self.position;
+        if self.word() != expected {
+            return Err(self.error_at(start, format!("expected '{}'", expected)));
+        }
+
+        Ok(())
+    }
+
+    /// `P` and its number
+    fn procedure(&mut self) -> Result<Procedure, ParseError> {
+        self.expect('P')?;
+        let start = self.position - 1;
+        let number = self.number()?;
+
+        Procedure::ALL
+            .into_iter()
+            .find(|procedure| *procedure as usize + 1 == number as usize)
+            .ok_or_else(|| self.error_at(start, format!("unknown procedure P{}", number)))
+    }
+
+    fn condition(&mut self) -> Result<Condition, ParseError> {
+        self.peek();
+        let start = self.position;
+        match self.word().as_str() {
+            "not" => Ok(Condition::Not(Box::new(self.condition()?))),
+            "unmowed" => {
+                self.expect_word("ahead")?;
+                Ok(Condition::AheadUnmowed)
+            }
+            "at" => {
+                self.expect_word("edge")?;
+                Ok(Condition::AtEdge)
+            }
+            _ => Err(self.error_at(start, "expected a condition")),
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Command>, ParseError> {
+        self.expect('(')?;
+        let commands = self.commands()?;
+        self.expect(')')?;
+
+        Ok(commands)
+    }
+
+    /// Reads commands up to the end of the block, procedure or text
+    fn commands(&mut self) -> Result<Vec<Command>, ParseError> {
+        let mut commands = vec![];
+        while let Some(c) = self.peek() {
+            let start = self.position;
+            match c {
+                ')' | ';' => break,
+                'U' | 'D' | 'L' | 'R' => {
+                    let direction = match c {
+                        'U' => HarvestorCommands::Up,
+                        'D' => HarvestorCommands::Down,
+                        'L' => HarvestorCommands::Left,
+                        _ => HarvestorCommands::Right,
+                    };
+                    self.position += 1;
+                    let times = match self.chars.get(self.position) {
+                        Some(c) if c.is_ascii_digit() => self.count()?,
+                        _ => 1,
+                    };
+                    commands.extend((0..times).map(|_| Command::Move(direction.clone())));
+                }
+                'F' | '<' | '>' | 'B' => {
+                    let steering = match c {
+                        'F' => Steering::Forward,
+                        '<' => Steering::TurnLeft,
+                        '>' => Steering::TurnRight,
+                        _ => Steering::Reverse,
+                    };
+                    self.position += 1;
+                    let times = match self.chars.get(self.position) {
+                        Some(c) if c.is_ascii_digit() => self.count()?,
+                        _ => 1,
+                    };
+                    commands.extend((0..times).map(|_| Command::Steer(steering)));
+                }
+                '^' => {
+                    self.position += 1;
+                    commands.push(Command::RaiseHeader);
+                }
+                '_' => {
+                    self.position += 1;
+                    commands.push(Command::LowerHeader);
+                }
+                'P' => commands.push(Command::Call(self.procedure()?)),
+                '(' => {
+                    let body = self.block()?;
+                    if self.peek() != Some('x') {
+                        return Err(self.error("expected 'x' and a count after the block"));
+                    }
+                    self.position += 1;
+                    commands.push(Command::Repeat(self.count()?, body));
+                }
+                c if c.is_ascii_lowercase() => match self.word().as_str() {
+                    "if" => {
+                        let condition = self.condition()?;
+                        let then = self.block()?;
+                        let before_else = self.position;
+                        let otherwise = if self.word() == "else" {
+                            self.block()?
+                        } else {
+                            self.position = before_else;
+                            vec![]
+                        };
+                        commands.push(Command::If(condition, then, otherwise));
+                    }
+                    "while" => {
+                        let condition = self.condition()?;
+                        commands.push(Command::While(condition, self.block()?));
+                    }
+                    word => return Err(self.error_at(start, format!("unknown command '{}'", word))),
+                },
+                c => return Err(self.error(format!("unknown command '{}'", c))),
+            }
+        }
+
+        Ok(commands)
+    }
+
+    /// The main commands, then every procedure as `; P1: commands`
+    fn program(&mut self) -> Result<Program, ParseError> {
+        let mut program = Program {
+            main: self.commands()?,
+            ..Default::default()
+        };
+        let mut defined = vec![];
+        while self.peek() == Some(';') {
+            self.position += 1;
+            self.peek();
+            let start = self.position;
+            let procedure = self.procedure()?;
+            if defined.contains(&procedure) {
+                return Err(self.error_at(start, format!("{} is defined twice", procedure.name())));
+            }
+            defined.push(procedure);
+            self.expect(':')?;
+            program.procedures[procedure as usize] = self.commands()?;
+        }
+
+        match self.peek() {
+            None => Ok(program),
+            Some(')') => Err(self.error("')' without a '('")),
+            Some(c) => Err(self.error(format!("unexpected '{}'", c))),
+        }
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Parser {
+            chars: text.chars().collect(),
+            position: 0,
+        }
+        .program()
+    }
+}
+
+fn direction_letter(direction: &HarvestorCommands) -> char {
+    match direction {
+        HarvestorCommands::Up => 'U',
+        HarvestorCommands::Down => 'D',
+        HarvestorCommands::Left => 'L',
+        HarvestorCommands::Right => 'R',
+    }
+}
+
+/// Writes commands the way [`Parser`] reads them, moves in the same direction are counted
+fn fmt_commands(f: &mut fmt::Formatter<'_>, commands: &[Command]) -> fmt::Result {
+    let mut index = 0;
+    while let Some(command) = commands.get(index) {
+        if index > 0 {
+            write!(f, " ")?;
+        }
+        index += 1;
+
+        match command {
+            Command::Move(_) | Command::Steer(_) => {
+                let times = 1 + commands[index..]
+                    .iter()
+                    .take_while(|next| *next == command)
+                    .count();
+                index += times - 1;
+                let letter = match command {
+                    Command::Move(direction) => direction_letter(direction),
+                    Command::Steer(steering) => steering.letter(),
+                    _ => unreachable!(),
+                };
+                write!(f, "{}", letter)?;
+                if times > 1 {
+                    write!(f, "{}", times)?;
+                }
+            }
+            Command::Repeat(times, body) => {
+                write!(f, "(")?;
+                fmt_commands(f, body)?;
+                write!(f, ")x{}", times)?;
+            }
+            Command::RaiseHeader => write!(f, "^")?,
+            Command::LowerHeader => write!(f, "_")?,
+            Command::Call(procedure) => write!(f, "{}", procedure.name())?,
+            Command::If(condition, then, otherwise) => {
+                write!(f, "if {} (", condition)?;
+                fmt_commands(f, then)?;
+                write!(f, ")")?;
+                if !otherwise.is_empty() {
+                    write!(f, " else (")?;
+                    fmt_commands(f, otherwise)?;
+                    write!(f, ")")?;
+                }
+            }
+            Command::While(condition, body) => {
+                write!(f, "while {} (", condition)?;
+                fmt_commands(f, body)?;
+                write!(f, ")")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the program in the text form it's parsed from, procedures that are empty are left out
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_commands(f, &self.main)?;
+        for procedure in Procedure::ALL {
+            let commands = self.procedure(procedure);
+            if !commands.is_empty() {
+                write!(f, "; {}: ", procedure.name())?;
+                fmt_commands(f, commands)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs a program on a simulation, conditions are decided once the harvestor gets to them
+struct Interpreter<'a, 'b> {
+    program: &'a Program,
+    simulation: Simulation<'b>,
+    /// Commands and loop rounds run so far
+    run_length: usize,
+    /// Every move that ran, next to the index of the command in the main program it came from
+    moves: Vec<(usize, HarvestorCommands)>,
+}
+
+impl<'a, 'b> Interpreter<'a, 'b> {
+    /// Returns `false` once the program has to stop
+    fn run(&mut self, body: &[Command], origin: Option<usize>, depth: usize) -> bool {
+        body.iter()
+            .enumerate()
+            .all(|(index, command)| self.run_command(command, origin.unwrap_or(index), depth))
+    }
+
+    /// Runs one round of a loop, every round counts so a loop that doesn't move still ends
+    fn run_round(&mut self, body: &[Command], origin: usize, depth: usize) -> bool {
+        self.run_length += 1;
+        self.run_length <= MAX_RUN_LENGTH && self.run(body, Some(origin), depth)
+    }
+
+    fn run_command(&mut self, command: &Command, origin: usize, depth: usize) -> bool {
+        self.run_length += 1;
+        if self.run_length > MAX_RUN_LENGTH {
+            return false;
+        }
+
+        match command {
+            Command::Move(direction) => {
+                self.moves.push((origin, direction.clone()));
+                self.simulation.step(origin, direction)
+            }
+            Command::Steer(steering) => {
+                let facing = self.simulation.trace().facing.clone();
+                match steering {
+                    Steering::Forward => {
+                        self.moves.push((origin, facing.clone()));
+                        self.simulation.step(origin, &facing)
+                    }
+                    Steering::TurnLeft => self.simulation.turn(origin, &facing.turned_left()),
+                    Steering::TurnRight => self.simulation.turn(origin, &facing.turned_right()),
+                    Steering::Reverse => {
+                        self.moves.push((origin, facing.reversed()));
+                        self.simulation.reverse(origin)
+                    }
+                }
+            }
+            Command::RaiseHeader => self.simulation.set_header(origin, true),
+            Command::LowerHeader => self.simulation.set_header(origin, false),
+            Command::Repeat(times, body) => {
+                (0..*times).all(|_| self.run_round(body, origin, depth))
+            }
+            Command::Call(procedure) => {
+                let program = self.program;
+                depth >= MAX_CALL_DEPTH
+                    || self.run(program.procedure(*procedure), Some(origin), depth + 1)
+            }
+            Command::If(condition, then, otherwise) => {
+                if condition.holds(&self.simulation) {
+                    self.run(then, Some(origin), depth)
+                } else {
+                    self.run(otherwise, Some(origin), depth)
+                }
+            }
+            Command::While(condition, body) => {
+                while condition.holds(&self.simulation) {
+                    if !self.run_round(body, origin, depth) {
+                        return false;
+                    }
+                }
+                true
+            }
+        }
+    }
+}
+
+fn interpret<'a, 'b>(program: &'a Program, simulation: Simulation<'b>) -> Interpreter<'a, 'b> {
+    let mut interpreter = Interpreter {
+        program,
+        simulation,
+        run_length: 0,
+        moves: vec![],
+    };
+    interpreter.run(&program.main, None, 0);
+
+    interpreter
+}
+
+/// Runs a program the way the harvestor does. The command indices in the trace point at the
+/// commands of the main program, so a crash inside a block or procedure blames the whole block
+/// or the call.
+pub fn run(start: IVec2, facing: HarvestorCommands, program: &Program, field: &FieldSpec) -> Trace {
+    interpret(program, Simulation::new(start, facing, field))
+        .simulation
+        .finish()
+}
+
+/// Like [`run`], but keeps the timeline of the run so it can be rewound
+pub fn run_recorded(
+    start: IVec2,
+    facing: HarvestorCommands,
+    program: &Program,
+    field: &FieldSpec,
+) -> Trace {
+    interpret(program, Simulation::new(start, facing, field).recording())
+        .simulation
+        .finish()
+}
+
+/// Every move the program runs, with blocks, calls and conditions worked out. Next to every move
+/// is the index of the command in the main program it came from.
+pub fn expand(
+    start: IVec2,
+    facing: HarvestorCommands,
+    program: &Program,
+    field: &FieldSpec,
+) -> Vec<(usize, HarvestorCommands)> {
+    interpret(program, Simulation::new(start, facing, field)).moves
+}
+
+#[cfg(test)]
+use crate::field::Obstacle;
+#[cfg(test)]
+use crate::simulation::{Crash, CrashReason};
+#[cfg(test)]
+use bevy::math::UVec2;
+
+#[cfg(test)]
+fn moves_on_open_field(program: &Program) -> Vec<(usize, HarvestorCommands)> {
+    let field = FieldSpec {
+        size: UVec2::new(7, 7),
+        ..Default::default()
+    };
+
+    expand(IVec2::new(2, -1), HarvestorCommands::Up, program, &field)
+}
+
+#[test]
+fn repeat_blocks_expand_in_place() {
+    use HarvestorCommands::*;
+    let program = Program {
+        main: vec![
+            Command::Move(Up),
+            Command::Repeat(2, vec![Command::Move(Right), Command::Move(Up)]),
+            Command::Repeat(2, vec![Command::Repeat(2, vec![Command::Move(Left)])]),
+        ],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        moves_on_open_field(&program),
+        vec![
+            (0, Up),
+            (1, Right),
+            (1, Up),
+            (1, Right),
+            (1, Up),
+            (2, Left),
+            (2, Left),
+            (2, Left),
+            (2, Left),
+        ]
+    );
+    assert_eq!(program.size(), 1 + 3 + 3);
+}
+
+#[test]
+fn crash_in_repeat_blames_the_block() {
+    use HarvestorCommands::*;
+    let field = FieldSpec {
+        size: UVec2::new(1, 3),
+        ..Default::default()
+    };
+    let program = Program {
+        main: vec![
+            Command::Move(Up),
+            Command::Repeat(5, vec![Command::Move(Up)]),
+        ],
+        ..Default::default()
+    };
+
+    let trace = run(IVec2::new(0, -1), Up, &program, &field);
+
+    assert_eq!(
+        trace.crash,
+        Some(Crash {
+            reason: CrashReason::OutOfBounds,
+            command_index: 1
+        })
+    );
+    assert_eq!(trace.position(), IVec2::new(0, 2));
+}
+
+#[test]
+fn nested_repeats_stop_at_the_run_length_cap() {
+    use HarvestorCommands::*;
+    let program = Program {
+        main: vec![
+            Command::Move(Up),
+            Command::Repeat(
+                9,
+                vec![Command::Repeat(
+                    9,
+                    vec![Command::Repeat(
+                        9,
+                        vec![Command::Repeat(
+                            9,
+                            vec![Command::Repeat(
+                                9,
+                                vec![Command::Move(Right), Command::Move(Left)],
+                            )],
+                        )],
+                    )],
+                )],
+            ),
+        ],
+        ..Default::default()
+    };
+
+    let moves = moves_on_open_field(&program);
+    assert!(moves.len() > MAX_RUN_LENGTH / 2);
+    assert!(moves.len() <= MAX_RUN_LENGTH);
+}
+
+#[test]
+fn procedures_expand_where_they_are_called() {
+    use HarvestorCommands::*;
+    let program = "P1 U P1; P1: U P2; P2: (R)x2".parse::<Program>().unwrap();
+
+    assert_eq!(
+        moves_on_open_field(&program),
+        vec![
+            (0, Up),
+            (0, Right),
+            (0, Right),
+            (1, Up),
+            (2, Up),
+            (2, Right),
+            (2, Right),
+        ]
+    );
+    assert_eq!(program.size(), 3 + 2 + 2);
+}
+
+#[test]
+fn endless_programs_stop() {
+    use HarvestorCommands::*;
+    let field = FieldSpec {
+        size: UVec2::new(1, 20),
+        ..Default::default()
+    };
+    let mut program = Program {
+        main: vec![Command::Call(Procedure::P1)],
+        procedures: [
+            vec![Command::Move(Up), Command::Call(Procedure::P1)],
+            vec![],
+        ],
+    };
+    let moves = |program: &Program| expand(IVec2::new(0, -1), Up, program, &field);
+    assert_eq!(moves(&program).len(), MAX_CALL_DEPTH);
+
+    // never moves, so the condition holds forever
+    program.main = vec![Command::While(Condition::AtEdge.negated(), vec![])];
+    assert!(moves(&program).is_empty());
+    assert_eq!(run(IVec2::new(0, -1), Up, &program, &field).crash, None);
+}
+
+#[test]
+fn conditions_look_at_the_square_ahead() {
+    use HarvestorCommands::*;
+    let mut field = FieldSpec {
+        size: UVec2::new(3, 3),
+        ..Default::default()
+    };
+    field.obstacles.insert((0, 2), Obstacle::Rock);
+    let mut simulation = Simulation::new(IVec2::new(0, -1), Up, &field);
+    let sensed = |simulation: &Simulation| {
+        (
+            Condition::AheadUnmowed.holds(simulation),
+            Condition::AtEdge.holds(simulation),
+        )
+    };
+
+    assert_eq!(sensed(&simulation), (true, false));
+    simulation.step(0, &Up);
+    simulation.step(1, &Up);
+    // the rock
+    assert_eq!(sensed(&simulation), (false, false));
+    simulation.step(2, &Right);
+    simulation.step(3, &Right);
+    assert_eq!(sensed(&simulation), (false, true));
+    assert!(!Condition::AtEdge.negated().holds(&simulation));
+    simulation.step(4, &Down);
+    simulation.step(5, &Left);
+    // harvested on the way up
+    assert_eq!(sensed(&simulation), (false, false));
+}
+
+#[test]
+fn conditions_decide_at_runtime() {
+    use HarvestorCommands::*;
+    let field = FieldSpec {
+        size: UVec2::new(2, 3),
+        ..Default::default()
+    };
+    let program = "while not at edge (U) if unmowed ahead (U) else (R)"
+        .parse::<Program>()
+        .unwrap();
+
+    assert_eq!(
+        expand(IVec2::new(0, -1), Up, &program, &field),
+        vec![(0, Up), (0, Up), (0, Up), (1, Right)]
+    );
+    assert_eq!(program.size(), 2 + 3);
+}
+
+#[test]
+fn program_text_round_trip() {
+    use HarvestorCommands::*;
+    let text = "U3 R L2 (U R)x4 P2; P1: if not unmowed ahead (D) else (while at edge (L)); P2: P1";
+    let program = text.parse::<Program>().unwrap();
+
+    assert_eq!(
+        program.main,
+        vec![
+            Command::Move(Up),
+            Command::Move(Up),
+            Command::Move(Up),
+            Command::Move(Right),
+            Command::Move(Left),
+            Command::Move(Left),
+            Command::Repeat(4, vec![Command::Move(Up), Command::Move(Right)]),
+            Command::Call(Procedure::P2),
+        ]
+    );
+    assert_eq!(program.to_string(), text);
+    assert_eq!(
+        "UU  RRR".parse::<Program>().unwrap().to_string(),
+        "U2 R3".to_string()
+    );
+    assert_eq!("".parse::<Program>().unwrap(), Program::from_moves(&[]));
+}
+
+#[test]
+fn program_text_errors_name_the_column() {
+    let column = |text: &str| text.parse::<Program>().unwrap_err().column;
+
+    assert_eq!(column("U R X"), 5);
+    assert_eq!(column("U (R U"), 7);
+    assert_eq!(column("(R U) L"), 7);
+    assert_eq!(column("U0"), 2);
+    assert_eq!(column("U R) L"), 4);
+    assert_eq!(column("P3"), 1);
+    assert_eq!(column("if ahead (U)"), 4);
+    assert_eq!(column("; P1: U; P1: R"), 10);
+    assert_eq!(
+        "U jump".parse::<Program>().unwrap_err().to_string(),
+        "column 3: unknown command 'jump'".to_string()
+    );
+}
+
+#[test]
+fn steering_follows_the_facing() {
+    use HarvestorCommands::*;
+    let field = FieldSpec {
+        size: UVec2::new(3, 3),
+        ..Default::default()
+    };
+    let program = "F > F2 B <".parse::<Program>().unwrap();
+    assert_eq!(program.to_string(), "F > F2 B <");
+
+    assert_eq!(
+        expand(IVec2::new(0, -1), Up, &program, &field),
+        vec![(0, Up), (2, Right), (3, Right), (4, Left)]
+    );
+    let trace = run(IVec2::new(0, -1), Up, &program, &field);
+    assert_eq!(trace.position(), IVec2::new(1, 0));
+    assert_eq!(trace.facing, Up);
+
+    let route = [Up, Right, Right, Left];
+    assert_eq!(
+        steer(Up, &route, true),
+        vec![
+            Steering::Forward,
+            Steering::TurnRight,
+            Steering::Forward,
+            Steering::Forward,
+            Steering::Reverse
+        ]
+    );
+    assert_eq!(steer(Up, &route, false).len(), 7);
+}
+
+#[test]
+fn raised_header_drives_without_harvesting() {
+    use HarvestorCommands::*;
+    let field = FieldSpec {
+        size: UVec2::new(1, 4),
+        ..Default::default()
+    };
+    let program = "U ^ U2 _ U; P1: (^ _)x2".parse::<Program>().unwrap();
+    assert_eq!(program.to_string(), "U ^ U2 _ U; P1: (^ _)x2");
+
+    let trace = run(IVec2::new(0, -1), Up, &program, &field);
+    let mowed = (0..4)
+        .map(|y| trace.mowed.contains_key(&(0, y)))
+        .collect::<Vec<_>>();
+    assert_eq!(mowed, vec![true, false, false, true]);
+    // P1 is never called
+    assert_eq!(trace.header_toggles(), 2);
+
+    // every round of a loop counts
+    let program = "(U ^ U _)x2".parse::<Program>().unwrap();
+    assert_eq!(
+        run(IVec2::new(0, -1), Up, &program, &field).header_toggles(),
+        4
+    );
+}
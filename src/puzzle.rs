@@ -0,0 +1,709 @@
+use crate::field::Obstacle;
+use crate::harvestor::{
+    command_to_grid_offset, HarvestorCommands, HarvestorState, KeyboardInput, NextBoardEvent,
+};
+use crate::level::{ActiveLevel, Level, LevelErrors};
+use crate::program::{run, steer, Command, Program, Steering};
+use crate::simulation::{Crash, CrashReason, FieldSpec, HeaderWidth};
+use crate::solver::solve;
+use crate::ui::{update_help_text, FontHandle, HelpTextContainer, SeedTextMarker};
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use itertools::Itertools;
+use iyes_loopless::prelude::AppLooplessStateExt;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::str::FromStr;
+
+pub struct PuzzlePlugin;
+
+impl Plugin for PuzzlePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(PuzzleSeed::from_args())
+            .insert_resource(Difficulty::from_args())
+            .add_enter_system(HarvestorState::AcceptingCommands, generate_level)
+            .add_system(fall_back_on_level_error)
+            .add_system(advance_seed.after(KeyboardInput))
+            .add_system(update_seed_text);
+    }
+}
+
+/// Seed every random draw of a board is made from.
+/// Start the game with `--seed <number>` to load a specific board.
+pub struct PuzzleSeed(pub u64);
+
+pub fn arg_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+impl PuzzleSeed {
+    fn from_args() -> Self {
+        match arg_value("--seed").and_then(|seed| seed.parse().ok()) {
+            Some(seed) => PuzzleSeed(seed),
+            None => PuzzleSeed(rand::thread_rng().gen()),
+        }
+    }
+
+    pub fn rng(&self) -> StdRng {
+        StdRng::seed_from_u64(self.0)
+    }
+
+    /// Moves on to the next board, derived from the current seed so a whole session can be replayed
+    pub fn advance(&mut self) {
+        self.0 = self.rng().gen();
+    }
+}
+
+/// Field size, how winding the path through it is and how many obstacles are in the way.
+/// Start the game with `--difficulty easy|normal|hard` or a custom `--difficulty 12x8:30:20:4`,
+/// being the field size, the length of the path, the percentage of steps that may turn and
+/// optionally the amount of obstacles.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Custom {
+        size: UVec2,
+        walk_length: u32,
+        chance_of_redirect: u32,
+        obstacles: u32,
+    },
+}
+
+impl Difficulty {
+    fn from_args() -> Self {
+        arg_value("--difficulty")
+            .and_then(|difficulty| difficulty.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn size(&self) -> UVec2 {
+        match self {
+            Difficulty::Easy => UVec2::new(6, 6),
+            Difficulty::Normal => UVec2::new(10, 10),
+            Difficulty::Hard => UVec2::new(14, 14),
+            Difficulty::Custom { size, .. } => *size,
+        }
+    }
+
+    pub fn walk_length(&self) -> u32 {
+        match self {
+            Difficulty::Easy => 10,
+            Difficulty::Normal => 25,
+            Difficulty::Hard => 50,
+            Difficulty::Custom { walk_length, .. } => *walk_length,
+        }
+    }
+
+    pub fn chance_of_redirect(&self) -> u32 {
+        match self {
+            Difficulty::Easy => 15,
+            Difficulty::Normal => 25,
+            Difficulty::Hard => 40,
+            Difficulty::Custom {
+                chance_of_redirect, ..
+            } => *chance_of_redirect,
+        }
+    }
+
+    pub fn obstacles(&self) -> u32 {
+        match self {
+            Difficulty::Easy => 0,
+            Difficulty::Normal => 3,
+            Difficulty::Hard => 8,
+            Difficulty::Custom { obstacles, .. } => *obstacles,
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => return Ok(Difficulty::Easy),
+            "normal" => return Ok(Difficulty::Normal),
+            "hard" => return Ok(Difficulty::Hard),
+            _ => {}
+        }
+
+        let invalid = || {
+            format!(
+                "invalid difficulty '{}', expected easy, normal, hard or WxH:length:chance[:obstacles]",
+                s
+            )
+        };
+        let (size, rest) = s.split_once(':').ok_or_else(invalid)?;
+        let (walk_length, rest) = rest.split_once(':').ok_or_else(invalid)?;
+        let (chance_of_redirect, obstacles) = rest.split_once(':').unwrap_or((rest, "0"));
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+
+        let size = UVec2::new(
+            width.parse().map_err(|_| invalid())?,
+            height.parse().map_err(|_| invalid())?,
+        );
+        if size.x == 0 || size.y == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Difficulty::Custom {
+            size,
+            walk_length: walk_length.parse().map_err(|_| invalid())?,
+            chance_of_redirect: chance_of_redirect.parse().map_err(|_| invalid())?,
+            obstacles: obstacles.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+fn advance_seed(
+    mut ev_next_board: EventReader<NextBoardEvent>,
+    mut seed: ResMut<PuzzleSeed>,
+    active_level: Res<ActiveLevel>,
+) {
+    for _ in ev_next_board.iter() {
+        if active_level.random {
+            seed.advance();
+        }
+    }
+}
+
+/// Shows the seed of a generated board, or the name of a level loaded from a file
+fn update_seed_text(
+    seed: Res<PuzzleSeed>,
+    difficulty: Res<Difficulty>,
+    active_level: Res<ActiveLevel>,
+    levels: Res<Assets<Level>>,
+    mut level_events: EventReader<AssetEvent<Level>>,
+    mut seed_text_q: Query<&mut Text, With<SeedTextMarker>>,
+) {
+    // a level file names the board once it's loaded, the editor modifies levels every frame
+    let level_loaded = level_events
+        .iter()
+        .any(|event| matches!(event, AssetEvent::Created { .. }));
+    if !(seed.is_changed() || difficulty.is_changed() || active_level.is_changed() || level_loaded)
+    {
+        return;
+    }
+
+    let board_text = if active_level.random {
+        let difficulty_name = match *difficulty {
+            Difficulty::Easy => "Easy".to_string(),
+            Difficulty::Normal => "Normal".to_string(),
+            Difficulty::Hard => "Hard".to_string(),
+            Difficulty::Custom { size, .. } => format!("Custom {}x{}", size.x, size.y),
+        };
+        format!("Seed: {} ({})", seed.0, difficulty_name)
+    } else {
+        levels
+            .get(&active_level.handle)
+            .and_then(|level| level.name())
+            .unwrap_or("Level")
+            .to_string()
+    };
+
+    seed_text_q.iter_mut().for_each(|mut text| {
+        text.sections[0].value = board_text.clone();
+    });
+}
+
+pub const HARVESTOR_START: IVec2 = IVec2::new(0, -1);
+pub const HARVESTOR_START_FACING: HarvestorCommands = HarvestorCommands::Left;
+
+/// A board to play, only constructed through [`Puzzle::new`] so it's always solvable.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Puzzle {
+    pub size: UVec2,
+    pub start: IVec2,
+    pub facing: HarvestorCommands,
+    /// Same layout as the target `Field`: squares set to `false` have to be harvested
+    pub target: HashMap<(i32, i32), bool>,
+    /// Squares the harvestor can't drive onto, these are never harvested
+    pub obstacles: HashMap<(i32, i32), Obstacle>,
+    /// A program that harvests exactly the target, empty if the board came without one
+    pub solution: Program,
+    pub controls: Controls,
+    /// How many commands may raise or lower the header, `None` when there's no limit
+    pub header_toggles: Option<usize>,
+    pub header_width: HeaderWidth,
+}
+
+/// How the arrow keys steer the harvestor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Controls {
+    /// Every arrow drives towards its side of the screen
+    #[default]
+    Absolute,
+    /// Arrows drive forward and turn, seen from the harvestor. Down backs up when `reverse` is
+    /// allowed.
+    Relative { reverse: bool },
+}
+
+impl Controls {
+    /// The command an arrow key enters, `None` for down when backing up isn't allowed
+    pub fn command(&self, arrow: HarvestorCommands) -> Option<Command> {
+        match (self, arrow) {
+            (Controls::Absolute, arrow) => Some(Command::Move(arrow)),
+            (Controls::Relative { .. }, HarvestorCommands::Up) => {
+                Some(Command::Steer(Steering::Forward))
+            }
+            (Controls::Relative { .. }, HarvestorCommands::Left) => {
+                Some(Command::Steer(Steering::TurnLeft))
+            }
+            (Controls::Relative { .. }, HarvestorCommands::Right) => {
+                Some(Command::Steer(Steering::TurnRight))
+            }
+            (Controls::Relative { reverse }, HarvestorCommands::Down) => {
+                reverse.then_some(Command::Steer(Steering::Reverse))
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PuzzleError {
+    /// The solution drives the harvestor off the field at this command
+    OutOfBounds(usize),
+    /// The solution drives the harvestor into an obstacle at this command
+    HitsObstacle(usize),
+    /// The solution does not harvest the same squares as the target
+    TargetMismatch,
+    /// The solution raises or lowers the header this many times, more often than allowed
+    TooManyHeaderToggles(usize),
+}
+
+impl Puzzle {
+    pub fn new(
+        size: UVec2,
+        start: IVec2,
+        facing: HarvestorCommands,
+        mut target: HashMap<(i32, i32), bool>,
+        obstacles: HashMap<(i32, i32), Obstacle>,
+        solution: Program,
+        header_width: HeaderWidth,
+    ) -> Result<Self, PuzzleError> {
+        for position in obstacles.keys() {
+            target.insert(*position, true);
+        }
+        let puzzle = Puzzle {
+            size,
+            start,
+            facing,
+            target,
+            obstacles,
+            solution,
+            controls: Controls::Absolute,
+            header_toggles: None,
+            header_width,
+        };
+        puzzle.validate()?;
+
+        Ok(puzzle)
+    }
+
+    /// Drives a random walk of `amount` steps from `start` that never leaves the field
+    pub fn generate(
+        rng: &mut impl Rng,
+        start: IVec2,
+        facing: HarvestorCommands,
+        size: UVec2,
+        amount: u32,
+        chance_of_redirect: u32,
+    ) -> Self {
+        let mut target = HashMap::new();
+        (0..size.x).cartesian_product(0..size.y).for_each(|(x, y)| {
+            target.insert((x as i32, y as i32), true);
+        });
+
+        let mut solution = vec![];
+        let mut position = start;
+        let mut walking_direction = facing.clone();
+
+        for _ in 0..amount {
+            let in_bounds = |command: &HarvestorCommands| {
+                is_in_bounds(position + command_to_grid_offset(command), size)
+            };
+            let options = [
+                HarvestorCommands::Up,
+                HarvestorCommands::Down,
+                HarvestorCommands::Left,
+                HarvestorCommands::Right,
+            ]
+            .into_iter()
+            .filter(|command| in_bounds(command))
+            .collect::<Vec<_>>();
+
+            if !in_bounds(&walking_direction) || rng.gen_range(0..100) < chance_of_redirect {
+                match options.choose(rng) {
+                    Some(command) => walking_direction = command.clone(),
+                    None => break,
+                }
+            }
+
+            solution.push(walking_direction.clone());
+            position += command_to_grid_offset(&walking_direction);
+            target.insert((position.x, position.y), false);
+        }
+
+        Puzzle::new(
+            size,
+            start,
+            facing,
+            target,
+            HashMap::new(),
+            Program::from_moves(&solution),
+            HeaderWidth::One,
+        )
+        .expect("a walk that stays within the field should always be solvable")
+    }
+
+    /// Puts up to `amount` obstacles on squares that are left standing, so the puzzle stays
+    /// solvable
+    pub fn place_obstacles(&mut self, rng: &mut impl Rng, amount: u32) {
+        let mut standing = (0..self.size.x as i32)
+            .cartesian_product(0..self.size.y as i32)
+            .filter(|coord| *self.target.get(coord).unwrap_or(&false))
+            .filter(|coord| !self.obstacles.contains_key(coord))
+            .collect::<Vec<_>>();
+        standing.shuffle(rng);
+
+        for coord in standing.into_iter().take(amount as usize) {
+            self.obstacles.insert(coord, rng.gen());
+        }
+    }
+
+    /// Length of the shortest program for this board that keeps the header lowered, or of the
+    /// solution it came with when the solver gives up. Levels with `header toggles` are built
+    /// around raising the header, so there the solution is the par when it's shorter.
+    pub fn par(&self) -> usize {
+        let shortest = solve(
+            &self.target,
+            &self.field_spec(),
+            self.start,
+            &self.facing,
+            &self.controls,
+        )
+        .map(|route| match self.controls {
+            Controls::Absolute => route.len(),
+            Controls::Relative { reverse } => steer(self.facing.clone(), &route, reverse).len(),
+        });
+        let header_allowed = matches!(self.header_toggles, Some(allowed) if allowed > 0);
+
+        match shortest {
+            Some(shortest) if header_allowed && self.solution.size() > 0 => {
+                shortest.min(self.solution.size())
+            }
+            Some(shortest) => shortest,
+            None => self.solution.size(),
+        }
+    }
+
+    /// Limits how often the header may be raised or lowered while a program runs, the solution
+    /// has to keep to it as well
+    pub fn with_header_toggles(mut self, allowed: Option<usize>) -> Result<Self, PuzzleError> {
+        self.header_toggles = allowed;
+        self.validate()?;
+
+        Ok(self)
+    }
+
+    /// How often running `program` raises or lowers the header and how often the board allows it,
+    /// when that's too often
+    pub fn excess_header_toggles(&self, program: &Program) -> Option<(usize, usize)> {
+        let allowed = self.header_toggles?;
+        let used =
+            run(self.start, self.facing.clone(), program, &self.field_spec()).header_toggles();
+
+        if used > allowed {
+            Some((used, allowed))
+        } else {
+            None
+        }
+    }
+
+    pub fn field_spec(&self) -> FieldSpec {
+        FieldSpec {
+            size: self.size,
+            obstacles: self.obstacles.clone(),
+            header_width: self.header_width,
+        }
+    }
+
+    /// Runs the solution the way the harvestor would
+    fn validate(&self) -> Result<(), PuzzleError> {
+        let trace = run(
+            self.start,
+            self.facing.clone(),
+            &self.solution,
+            &self.field_spec(),
+        );
+
+        match trace.crash {
+            Some(Crash {
+                reason: CrashReason::OutOfBounds,
+                command_index,
+            }) => return Err(PuzzleError::OutOfBounds(command_index)),
+            Some(Crash {
+                reason: CrashReason::Obstacle,
+                command_index,
+            }) => return Err(PuzzleError::HitsObstacle(command_index)),
+            None => {}
+        }
+        if self
+            .header_toggles
+            .map_or(false, |allowed| trace.header_toggles() > allowed)
+        {
+            return Err(PuzzleError::TooManyHeaderToggles(trace.header_toggles()));
+        }
+
+        if self.solution.size() == 0 {
+            return Ok(());
+        }
+
+        let matches_target = (0..self.size.x)
+            .cartesian_product(0..self.size.y)
+            .map(|(x, y)| (x as i32, y as i32))
+            .all(|coord| {
+                let should_harvest = !self.target.get(&coord).unwrap_or(&false);
+                should_harvest == trace.mowed.contains_key(&coord)
+            });
+
+        if matches_target {
+            Ok(())
+        } else {
+            Err(PuzzleError::TargetMismatch)
+        }
+    }
+}
+
+pub fn is_in_bounds(position: IVec2, size: UVec2) -> bool {
+    position.x >= 0 && position.y >= 0 && position.x < size.x as i32 && position.y < size.y as i32
+}
+
+fn generate_level(
+    seed: Res<PuzzleSeed>,
+    difficulty: Res<Difficulty>,
+    mut active_level: ResMut<ActiveLevel>,
+    mut levels: ResMut<Assets<Level>>,
+) {
+    if !active_level.random {
+        return;
+    }
+    active_level.handle = levels.add(generated_level(&seed, &difficulty));
+}
+
+fn generated_level(seed: &PuzzleSeed, difficulty: &Difficulty) -> Level {
+    let mut rng = seed.rng();
+
+    let mut puzzle = Puzzle::generate(
+        &mut rng,
+        HARVESTOR_START,
+        HARVESTOR_START_FACING,
+        difficulty.size(),
+        difficulty.walk_length(),
+        difficulty.chance_of_redirect(),
+    );
+    puzzle.place_obstacles(&mut rng, difficulty.obstacles());
+    Level::new(puzzle, HashMap::new())
+}
+
+/// Plays a generated board instead of a level file that failed to load, and says what's wrong
+/// with the file
+#[allow(clippy::too_many_arguments)]
+fn fall_back_on_level_error(
+    mut commands: Commands,
+    seed: Res<PuzzleSeed>,
+    difficulty: Res<Difficulty>,
+    mut active_level: ResMut<ActiveLevel>,
+    mut levels: ResMut<Assets<Level>>,
+    asset_server: Res<AssetServer>,
+    level_errors: Res<LevelErrors>,
+    help_ui_container_q: Query<Entity, With<HelpTextContainer>>,
+    font: Res<FontHandle>,
+) {
+    if asset_server.get_load_state(&active_level.handle) != LoadState::Failed {
+        return;
+    }
+
+    let help_text = match asset_server.get_handle_path(&active_level.handle) {
+        Some(path) => match level_errors.get(path.path()) {
+            Some(error) => format!(
+                "Couldn't load {}, {}. Playing a generated board instead.",
+                path.path().display(),
+                error
+            ),
+            None => format!(
+                "Couldn't load {}. Playing a generated board instead.",
+                path.path().display()
+            ),
+        },
+        None => "Couldn't load the level. Playing a generated board instead.".to_string(),
+    };
+    // later boards are generated from the next seeds as well
+    active_level.handle = levels.add(generated_level(&seed, &difficulty));
+    active_level.random = true;
+
+    let e = help_ui_container_q.single();
+    update_help_text(&font, &mut commands, e, &help_text);
+}
+
+#[test]
+fn generated_puzzle_stays_in_bounds() {
+    for seed in 0..100 {
+        let mut rng = PuzzleSeed(seed).rng();
+        let puzzle = Puzzle::generate(
+            &mut rng,
+            HARVESTOR_START,
+            HARVESTOR_START_FACING,
+            UVec2::new(10, 10),
+            25,
+            25,
+        );
+
+        assert!(puzzle.validate().is_ok());
+        assert!(puzzle
+            .target
+            .keys()
+            .all(|(x, y)| is_in_bounds(IVec2::new(*x, *y), puzzle.size)));
+    }
+}
+
+#[test]
+fn generated_solution_moves_on_every_command() {
+    for seed in 0..100 {
+        let mut rng = PuzzleSeed(seed).rng();
+        let puzzle = Puzzle::generate(
+            &mut rng,
+            HARVESTOR_START,
+            HARVESTOR_START_FACING,
+            UVec2::new(10, 10),
+            25,
+            25,
+        );
+
+        let mut position = puzzle.start;
+        for command in &puzzle.solution.main {
+            let direction = match command {
+                Command::Move(direction) => direction,
+                command => panic!("unexpected {:?} in a generated solution", command),
+            };
+            position += command_to_grid_offset(direction);
+            assert!(is_in_bounds(position, puzzle.size));
+            assert_eq!(puzzle.target.get(&(position.x, position.y)), Some(&false));
+        }
+    }
+}
+
+#[test]
+fn same_seed_generates_same_puzzle() {
+    let generate = |seed| {
+        Puzzle::generate(
+            &mut PuzzleSeed(seed).rng(),
+            HARVESTOR_START,
+            HARVESTOR_START_FACING,
+            UVec2::new(10, 10),
+            25,
+            25,
+        )
+    };
+
+    assert_eq!(generate(81723).solution, generate(81723).solution);
+    assert_eq!(generate(81723).target, generate(81723).target);
+}
+
+#[test]
+fn solution_leaving_field_is_rejected() {
+    let puzzle = Puzzle::new(
+        UVec2::new(2, 2),
+        IVec2::new(0, -1),
+        HarvestorCommands::Left,
+        HashMap::new(),
+        HashMap::new(),
+        Program::from_moves(&[HarvestorCommands::Left]),
+        HeaderWidth::One,
+    );
+
+    assert_eq!(puzzle.unwrap_err(), PuzzleError::OutOfBounds(0));
+}
+
+#[test]
+fn obstacles_stay_off_the_path() {
+    for seed in 0..100 {
+        let mut rng = PuzzleSeed(seed).rng();
+        let mut puzzle = Puzzle::generate(
+            &mut rng,
+            HARVESTOR_START,
+            HARVESTOR_START_FACING,
+            UVec2::new(6, 6),
+            10,
+            15,
+        );
+        puzzle.place_obstacles(&mut rng, 5);
+
+        assert_eq!(puzzle.obstacles.len(), 5);
+        assert!(puzzle.validate().is_ok());
+    }
+
+    let mut obstacles = HashMap::new();
+    obstacles.insert((0, 1), Obstacle::Rock);
+    let puzzle = Puzzle::new(
+        UVec2::new(2, 2),
+        IVec2::new(0, -1),
+        HarvestorCommands::Up,
+        HashMap::new(),
+        obstacles,
+        Program::from_moves(&[HarvestorCommands::Up, HarvestorCommands::Up]),
+        HeaderWidth::One,
+    );
+
+    assert_eq!(puzzle.unwrap_err(), PuzzleError::HitsObstacle(1));
+}
+
+#[test]
+fn parse_difficulty() {
+    assert_eq!("Hard".parse(), Ok(Difficulty::Hard));
+    assert_eq!(
+        "12x8:30:20".parse(),
+        Ok(Difficulty::Custom {
+            size: UVec2::new(12, 8),
+            walk_length: 30,
+            chance_of_redirect: 20,
+            obstacles: 0,
+        })
+    );
+    assert_eq!(
+        "12x8:30:20:4".parse(),
+        Ok(Difficulty::Custom {
+            size: UVec2::new(12, 8),
+            walk_length: 30,
+            chance_of_redirect: 20,
+            obstacles: 4,
+        })
+    );
+    assert!("0x8:30:20".parse::<Difficulty>().is_err());
+    assert!("impossible".parse::<Difficulty>().is_err());
+}
+
+#[test]
+fn generated_puzzle_fits_non_square_field() {
+    let difficulty = Difficulty::Custom {
+        size: UVec2::new(3, 12),
+        walk_length: 40,
+        chance_of_redirect: 50,
+        obstacles: 0,
+    };
+    let puzzle = Puzzle::generate(
+        &mut PuzzleSeed(7).rng(),
+        HARVESTOR_START,
+        HARVESTOR_START_FACING,
+        difficulty.size(),
+        difficulty.walk_length(),
+        difficulty.chance_of_redirect(),
+    );
+
+    assert_eq!(puzzle.target.len(), 36);
+    assert!(puzzle.validate().is_ok());
+}
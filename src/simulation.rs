@@ -0,0 +1,474 @@
+use crate::field::Obstacle;
+use crate::harvestor::{command_to_grid_offset, HarvestorCommands};
+use crate::puzzle::is_in_bounds;
+use bevy::math::{IVec2, UVec2};
+use bevy::utils::HashMap;
+
+/// The parts of a field a program depends on, without any entities or rendering
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldSpec {
+    pub size: UVec2,
+    pub obstacles: HashMap<(i32, i32), Obstacle>,
+    /// How wide the harvestor harvests, it's the same for the whole board
+    pub header_width: HeaderWidth,
+}
+
+impl FieldSpec {
+    /// The squares the header covers with the harvestor on `position`, across the direction it
+    /// faces. Squares off the field or under an obstacle are left out.
+    pub fn swath(&self, position: IVec2, facing: &HarvestorCommands) -> Vec<IVec2> {
+        let reach = self.header_width.reach();
+        let ahead = command_to_grid_offset(facing);
+        let across = IVec2::new(-ahead.y, ahead.x);
+        (-reach..=reach)
+            .map(|side| position + across * side)
+            .filter(|square| is_in_bounds(*square, self.size))
+            .filter(|square| !self.obstacles.contains_key(&(square.x, square.y)))
+            .collect()
+    }
+}
+
+/// How many squares the header harvests at once, centered on the harvestor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeaderWidth {
+    #[default]
+    One,
+    Three,
+    Five,
+}
+
+impl HeaderWidth {
+    pub fn squares(&self) -> i32 {
+        match self {
+            HeaderWidth::One => 1,
+            HeaderWidth::Three => 3,
+            HeaderWidth::Five => 5,
+        }
+    }
+
+    /// Squares the header sticks out on either side
+    pub fn reach(&self) -> i32 {
+        self.squares() / 2
+    }
+
+    pub fn from_squares(squares: i32) -> Option<Self> {
+        match squares {
+            1 => Some(HeaderWidth::One),
+            3 => Some(HeaderWidth::Three),
+            5 => Some(HeaderWidth::Five),
+            _ => None,
+        }
+    }
+}
+
+/// Why the harvestor stopped before running all commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashReason {
+    OutOfBounds,
+    Obstacle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crash {
+    pub reason: CrashReason,
+    /// Index of the command that caused the crash, in the order the player entered them
+    pub command_index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceStep {
+    /// Turned in place, a command in a new direction does this before it moves
+    Turn {
+        command_index: usize,
+        direction: HarvestorCommands,
+    },
+    /// Drove one square ahead, or back, and harvested it unless the header is raised
+    Move { command_index: usize, to: IVec2 },
+    /// Drove towards `to` and crashed, nothing runs after this
+    Crash { crash: Crash, to: IVec2 },
+    /// Raised or lowered the header, squares aren't harvested while it's raised
+    Header { command_index: usize, raised: bool },
+}
+
+impl TraceStep {
+    pub fn command_index(&self) -> usize {
+        match self {
+            TraceStep::Turn { command_index, .. }
+            | TraceStep::Move { command_index, .. }
+            | TraceStep::Header { command_index, .. } => *command_index,
+            TraceStep::Crash { crash, .. } => crash.command_index,
+        }
+    }
+}
+
+/// Where the harvestor was and what it had harvested at one point of a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub position: IVec2,
+    pub facing: HarvestorCommands,
+    pub mowed: HashMap<(i32, i32), bool>,
+    pub header_raised: bool,
+    /// The command that ran last, `None` at the start
+    pub command_index: Option<usize>,
+}
+
+/// Everything that happened while running a program
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+    /// Every square the harvestor stood on in order, starting with the start
+    pub visited: Vec<IVec2>,
+    /// Same layout as the canvas `Field`: harvested squares are `true`
+    pub mowed: HashMap<(i32, i32), bool>,
+    pub facing: HarvestorCommands,
+    /// The harvestor starts with the header lowered, so it harvests every square it drives onto
+    pub header_raised: bool,
+    pub crash: Option<Crash>,
+    /// A snapshot of the start and one after every command that moved, turned or raised or
+    /// lowered the header. Empty unless the simulation was [`Simulation::recording`].
+    pub timeline: Vec<Snapshot>,
+}
+
+impl Trace {
+    pub fn position(&self) -> IVec2 {
+        *self
+            .visited
+            .last()
+            .expect("a trace always contains the start")
+    }
+
+    /// How often the header was raised or lowered
+    pub fn header_toggles(&self) -> usize {
+        self.steps
+            .iter()
+            .filter(|step| matches!(step, TraceStep::Header { .. }))
+            .count()
+    }
+}
+
+/// Runs commands one at a time, so what runs next can depend on the field as it is by then
+pub struct Simulation<'a> {
+    field: &'a FieldSpec,
+    trace: Trace,
+    recording: bool,
+}
+
+impl<'a> Simulation<'a> {
+    pub fn new(start: IVec2, facing: HarvestorCommands, field: &'a FieldSpec) -> Self {
+        let mut trace = Trace {
+            visited: vec![start],
+            ..Default::default()
+        };
+        for square in field.swath(start, &facing) {
+            trace.mowed.insert((square.x, square.y), true);
+        }
+        trace.facing = facing;
+
+        Simulation {
+            field,
+            trace,
+            recording: false,
+        }
+    }
+
+    /// Keeps a [`Snapshot`] of every command in the timeline, only runs that can be rewound
+    /// need them
+    pub fn recording(mut self) -> Self {
+        self.recording = true;
+        self.record(None);
+
+        self
+    }
+
+    pub fn field(&self) -> &FieldSpec {
+        self.field
+    }
+
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    /// The square the harvestor drives onto when it keeps going in the direction it faces
+    pub fn ahead(&self) -> IVec2 {
+        self.trace.position() + command_to_grid_offset(&self.trace.facing)
+    }
+
+    /// A command in the current direction moves one square, a command in another direction
+    /// first turns and then moves. Returns `false` once the harvestor crashed, it doesn't move
+    /// anymore after that.
+    pub fn step(&mut self, command_index: usize, command: &HarvestorCommands) -> bool {
+        if self.trace.crash.is_some() {
+            return false;
+        }
+        self.face(command_index, command);
+
+        self.drive(command_index, command_to_grid_offset(command))
+    }
+
+    /// Turns in place to face `direction`
+    pub fn turn(&mut self, command_index: usize, direction: &HarvestorCommands) -> bool {
+        if self.trace.crash.is_some() {
+            return false;
+        }
+        self.face(command_index, direction);
+        self.record(Some(command_index));
+
+        true
+    }
+
+    /// Backs up one square, still facing the same way
+    pub fn reverse(&mut self, command_index: usize) -> bool {
+        if self.trace.crash.is_some() {
+            return false;
+        }
+
+        self.drive(command_index, -command_to_grid_offset(&self.trace.facing))
+    }
+
+    /// Raises or lowers the header, does nothing when it already is
+    pub fn set_header(&mut self, command_index: usize, raised: bool) -> bool {
+        if self.trace.crash.is_some() {
+            return false;
+        }
+        if self.trace.header_raised != raised {
+            self.trace.header_raised = raised;
+            self.trace.steps.push(TraceStep::Header {
+                command_index,
+                raised,
+            });
+            self.record(Some(command_index));
+        }
+
+        true
+    }
+
+    fn face(&mut self, command_index: usize, direction: &HarvestorCommands) {
+        let trace = &mut self.trace;
+        if *direction != trace.facing {
+            trace.facing = direction.clone();
+            trace.steps.push(TraceStep::Turn {
+                command_index,
+                direction: direction.clone(),
+            });
+        }
+    }
+
+    fn drive(&mut self, command_index: usize, offset: IVec2) -> bool {
+        let trace = &mut self.trace;
+        let to = trace.position() + offset;
+        let crash_reason = if !is_in_bounds(to, self.field.size) {
+            Some(CrashReason::OutOfBounds)
+        } else if self.field.obstacles.contains_key(&(to.x, to.y)) {
+            Some(CrashReason::Obstacle)
+        } else {
+            None
+        };
+
+        if let Some(reason) = crash_reason {
+            let crash = Crash {
+                reason,
+                command_index,
+            };
+            trace.steps.push(TraceStep::Crash { crash, to });
+            trace.crash = Some(crash);
+            return false;
+        }
+
+        trace.steps.push(TraceStep::Move { command_index, to });
+        trace.visited.push(to);
+        if !trace.header_raised {
+            for square in self.field.swath(to, &trace.facing) {
+                trace.mowed.insert((square.x, square.y), true);
+            }
+        }
+        self.record(Some(command_index));
+
+        true
+    }
+
+    fn record(&mut self, command_index: Option<usize>) {
+        if !self.recording {
+            return;
+        }
+        let snapshot = Snapshot {
+            position: self.trace.position(),
+            facing: self.trace.facing.clone(),
+            mowed: self.trace.mowed.clone(),
+            header_raised: self.trace.header_raised,
+            command_index,
+        };
+        self.trace.timeline.push(snapshot);
+    }
+
+    pub fn finish(self) -> Trace {
+        self.trace
+    }
+}
+
+/// Runs commands the way the harvestor does, see [`Simulation::step`]. Stops at the first
+/// command that would leave the field or drive into an obstacle.
+pub fn simulate(
+    start: IVec2,
+    facing: HarvestorCommands,
+    commands: &[HarvestorCommands],
+    field: &FieldSpec,
+) -> Trace {
+    let mut simulation = Simulation::new(start, facing, field);
+    for (command_index, command) in commands.iter().enumerate() {
+        if !simulation.step(command_index, command) {
+            break;
+        }
+    }
+
+    simulation.finish()
+}
+
+#[test]
+fn simulate_turns_before_moving() {
+    use HarvestorCommands::*;
+    let field = FieldSpec {
+        size: UVec2::new(3, 3),
+        ..Default::default()
+    };
+
+    let trace = simulate(IVec2::new(0, -1), Left, &[Up, Up, Right], &field);
+
+    assert_eq!(
+        trace.steps,
+        vec![
+            TraceStep::Turn {
+                command_index: 0,
+                direction: Up
+            },
+            TraceStep::Move {
+                command_index: 0,
+                to: IVec2::new(0, 0)
+            },
+            TraceStep::Move {
+                command_index: 1,
+                to: IVec2::new(0, 1)
+            },
+            TraceStep::Turn {
+                command_index: 2,
+                direction: Right
+            },
+            TraceStep::Move {
+                command_index: 2,
+                to: IVec2::new(1, 1)
+            },
+        ]
+    );
+    assert_eq!(trace.position(), IVec2::new(1, 1));
+    assert_eq!(trace.facing, Right);
+    assert_eq!(trace.mowed.len(), 3);
+    assert_eq!(trace.crash, None);
+}
+
+#[test]
+fn simulate_stops_at_crash() {
+    use HarvestorCommands::*;
+    let mut field = FieldSpec {
+        size: UVec2::new(2, 2),
+        ..Default::default()
+    };
+
+    let trace = simulate(IVec2::new(0, -1), Up, &[Up, Left, Up], &field);
+    assert_eq!(
+        trace.crash,
+        Some(Crash {
+            reason: CrashReason::OutOfBounds,
+            command_index: 1
+        })
+    );
+    assert_eq!(trace.position(), IVec2::new(0, 0));
+
+    field.obstacles.insert((0, 1), Obstacle::Tree);
+    let trace = simulate(IVec2::new(0, -1), Up, &[Up, Up, Right], &field);
+    assert_eq!(
+        trace.steps.last(),
+        Some(&TraceStep::Crash {
+            crash: Crash {
+                reason: CrashReason::Obstacle,
+                command_index: 1
+            },
+            to: IVec2::new(0, 1)
+        })
+    );
+    assert_eq!(trace.visited, vec![IVec2::new(0, -1), IVec2::new(0, 0)]);
+    assert!(trace.timeline.is_empty());
+}
+
+#[test]
+fn timeline_has_a_snapshot_per_command() {
+    use HarvestorCommands::*;
+    let field = FieldSpec {
+        size: UVec2::new(2, 2),
+        ..Default::default()
+    };
+
+    let mut simulation = Simulation::new(IVec2::new(0, -1), Up, &field).recording();
+    for (command_index, command) in [Up, Right, Up, Up].iter().enumerate() {
+        simulation.step(command_index, command);
+    }
+    let trace = simulation.finish();
+    // the crash at the last command doesn't add a snapshot
+    let positions = trace
+        .timeline
+        .iter()
+        .map(|snapshot| (snapshot.position, snapshot.command_index))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        positions,
+        vec![
+            (IVec2::new(0, -1), None),
+            (IVec2::new(0, 0), Some(0)),
+            (IVec2::new(1, 0), Some(1)),
+            (IVec2::new(1, 1), Some(2)),
+        ]
+    );
+    assert_eq!(trace.timeline[0].mowed.len(), 0);
+    assert_eq!(trace.timeline[2].mowed.len(), 2);
+    assert_eq!(trace.timeline[2].facing, Right);
+    assert_eq!(trace.timeline.last().unwrap().mowed, trace.mowed);
+}
+
+#[test]
+fn raised_header_leaves_squares_standing() {
+    use HarvestorCommands::*;
+    let field = FieldSpec {
+        size: UVec2::new(1, 3),
+        ..Default::default()
+    };
+
+    let mut simulation = Simulation::new(IVec2::new(0, -1), Up, &field).recording();
+    simulation.step(0, &Up);
+    simulation.set_header(1, true);
+    simulation.step(2, &Up);
+    simulation.set_header(3, false);
+    simulation.step(4, &Up);
+    let trace = simulation.finish();
+
+    assert_eq!(trace.mowed.get(&(0, 0)), Some(&true));
+    assert_eq!(trace.mowed.get(&(0, 1)), None);
+    assert_eq!(trace.mowed.get(&(0, 2)), Some(&true));
+    assert!(trace.timeline[2].header_raised);
+    assert_eq!(trace.timeline.len(), 6);
+}
+
+#[test]
+fn wide_header_harvests_across_the_facing() {
+    use HarvestorCommands::*;
+    let mut field = FieldSpec {
+        size: UVec2::new(4, 3),
+        header_width: HeaderWidth::Three,
+        ..Default::default()
+    };
+    field.obstacles.insert((3, 1), Obstacle::Rock);
+
+    let trace = simulate(IVec2::new(1, -1), Up, &[Up, Right, Up], &field);
+    let mut mowed = trace.mowed.keys().copied().collect::<Vec<_>>();
+    mowed.sort();
+    // the side squares off the field and under the rock are left out
+    assert_eq!(mowed, vec![(0, 0), (1, 0), (1, 1), (2, 0), (2, 1)]);
+    assert_eq!(trace.crash, None);
+}
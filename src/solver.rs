@@ -0,0 +1,371 @@
+use crate::harvestor::{command_to_grid_offset, HarvestorCommands};
+use crate::program::{steering_towards, Steering};
+use crate::puzzle::{Controls, Puzzle};
+use crate::simulation::{simulate, FieldSpec};
+use bevy::math::IVec2;
+use bevy::utils::HashMap;
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Gives up on boards where the search grows beyond this many states, such as a large field
+/// that has to be harvested entirely
+const MAX_SEARCHED_STATES: usize = 200_000;
+
+const DIRECTIONS: [HarvestorCommands; 4] = [
+    HarvestorCommands::Up,
+    HarvestorCommands::Down,
+    HarvestorCommands::Left,
+    HarvestorCommands::Right,
+];
+
+struct Node {
+    position: IVec2,
+    /// Only changes with relative controls, where turning costs commands
+    facing: HarvestorCommands,
+    /// Squares harvested so far, one bit per square that has to be harvested
+    harvested: u128,
+    /// Commands it took to get here
+    moves: usize,
+    parent: Option<(usize, HarvestorCommands)>,
+}
+
+/// Searches the route of moves that harvests exactly the squares set to `false` in `target`
+/// with the fewest commands.
+///
+/// With absolute controls every command moves one square, turning first when it's in a new
+/// direction, so the facing of the harvestor never costs a command and only the route matters.
+/// With relative controls turns are commands of their own, so the route is the one that
+/// [`steer`]s with the fewest. The header stays lowered, so the route may only cross squares
+/// that have to be harvested, anything else would leave the field, hit an obstacle or harvest
+/// too much, and neither may the sides of a wide header. Returns `None` when no such route
+/// exists or the search gives up.
+///
+/// [`steer`]: crate::program::steer
+pub fn solve(
+    target: &HashMap<(i32, i32), bool>,
+    field: &FieldSpec,
+    start: IVec2,
+    facing: &HarvestorCommands,
+    controls: &Controls,
+) -> Option<Vec<HarvestorCommands>> {
+    let mut mowed = HashMap::new();
+    // the harvestor already stands on its first square
+    for square in field.swath(start, facing) {
+        mowed.insert((square.x, square.y), true);
+    }
+
+    solve_from(target, field, start, facing, &mowed, controls)
+}
+
+/// Like [`solve`], but continues a run that already harvested the squares in `mowed`
+pub fn solve_from(
+    target: &HashMap<(i32, i32), bool>,
+    field: &FieldSpec,
+    start: IVec2,
+    facing: &HarvestorCommands,
+    mowed: &HashMap<(i32, i32), bool>,
+    controls: &Controls,
+) -> Option<Vec<HarvestorCommands>> {
+    let squares = (0..field.size.x as i32)
+        .cartesian_product(0..field.size.y as i32)
+        .filter(|coord| !target.get(coord).unwrap_or(&false))
+        .filter(|coord| !field.obstacles.contains_key(coord))
+        .map(|(x, y)| IVec2::new(x, y))
+        .collect::<Vec<_>>();
+    if squares.len() > u128::BITS as usize {
+        return None;
+    }
+    let bits = squares
+        .iter()
+        .enumerate()
+        .map(|(index, square)| (*square, 1u128 << index))
+        .collect::<HashMap<_, _>>();
+    let bit = |position: IVec2| bits.get(&position).copied();
+    let all_harvested = match squares.len() {
+        128 => u128::MAX,
+        amount => (1 << amount) - 1,
+    };
+
+    let mut harvested = 0;
+    for (&(x, y), _) in mowed.iter().filter(|(_, mowed)| **mowed) {
+        // a square that should stand can't be unharvested again
+        harvested |= bit(IVec2::new(x, y))?;
+    }
+
+    let mut nodes = vec![Node {
+        position: start,
+        facing: facing.clone(),
+        harvested,
+        moves: 0,
+        parent: None,
+    }];
+    let mut best_moves = HashMap::new();
+    best_moves.insert((start, facing.clone(), harvested), 0);
+    // ordered by the least amount of commands the program can end up with, which is at least
+    // one per header width of squares left to harvest
+    let width = field.header_width.squares() as usize;
+    let remaining =
+        |harvested: u128| ((all_harvested & !harvested).count_ones() as usize + width - 1) / width;
+    let mut open = BinaryHeap::new();
+    open.push(Reverse((remaining(harvested), remaining(harvested), 0)));
+
+    while let Some(Reverse((_, _, index))) = open.pop() {
+        let node = &nodes[index];
+        if node.harvested == all_harvested {
+            return Some(program(&nodes, index));
+        }
+        if best_moves.get(&(node.position, node.facing.clone(), node.harvested))
+            != Some(&node.moves)
+        {
+            continue;
+        }
+        if nodes.len() > MAX_SEARCHED_STATES {
+            return None;
+        }
+
+        let (position, facing) = (node.position, node.facing.clone());
+        let (harvested, moved) = (node.harvested, node.moves);
+        for command in DIRECTIONS {
+            let (commands, facing) = drive(controls, &facing, &command);
+            let moves = moved + commands;
+            let to = position + command_to_grid_offset(&command);
+            if bit(to).is_none() {
+                continue;
+            }
+            let harvested = match field
+                .swath(to, &command)
+                .into_iter()
+                .try_fold(harvested, |harvested, square| {
+                    Some(harvested | bit(square)?)
+                }) {
+                Some(harvested) => harvested,
+                None => continue,
+            };
+            if best_moves
+                .get(&(to, facing.clone(), harvested))
+                .map_or(false, |best| *best <= moves)
+            {
+                continue;
+            }
+
+            best_moves.insert((to, facing.clone(), harvested), moves);
+            open.push(Reverse((
+                moves + remaining(harvested),
+                remaining(harvested),
+                nodes.len(),
+            )));
+            nodes.push(Node {
+                position: to,
+                facing,
+                harvested,
+                moves,
+                parent: Some((index, command)),
+            });
+        }
+    }
+
+    None
+}
+
+/// How many commands it takes to move one square in `direction` and where the harvestor faces
+/// afterwards. With absolute controls the facing is left as it was, it never costs a command.
+fn drive(
+    controls: &Controls,
+    facing: &HarvestorCommands,
+    direction: &HarvestorCommands,
+) -> (usize, HarvestorCommands) {
+    match controls {
+        Controls::Absolute => (1, facing.clone()),
+        Controls::Relative { reverse } => {
+            let steering = steering_towards(facing, direction, *reverse);
+            if steering == [Steering::Reverse] {
+                (steering.len(), facing.clone())
+            } else {
+                (steering.len(), direction.clone())
+            }
+        }
+    }
+}
+
+/// What the hint key suggests for the commands entered so far
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hint {
+    /// Entering this command keeps the program on the way to a perfect harvest
+    Next(HarvestorCommands),
+    /// The field can't be harvested perfectly anymore once this command runs
+    Wrong(usize),
+    /// The commands already harvest the field perfectly
+    Run,
+}
+
+/// Keeps the longest part of `commands` that can still be finished into a perfect harvest and
+/// suggests how to go on from there. Returns `None` when the board has no solution at all, or
+/// none the search can find.
+pub fn hint(puzzle: &Puzzle, commands: &[HarvestorCommands]) -> Option<Hint> {
+    let field = puzzle.field_spec();
+    let crash = simulate(puzzle.start, puzzle.facing.clone(), commands, &field).crash;
+    let runs_until = crash.map_or(commands.len(), |crash| crash.command_index);
+
+    let finish = |kept: usize| {
+        let trace = simulate(
+            puzzle.start,
+            puzzle.facing.clone(),
+            &commands[..kept],
+            &field,
+        );
+        solve_from(
+            &puzzle.target,
+            &field,
+            trace.position(),
+            &trace.facing,
+            &trace.mowed,
+            &puzzle.controls,
+        )
+    };
+
+    // a part that can be finished can be cut shorter and finished the same way, so the longest
+    // one is found by halving the range in between
+    let (kept, rest) = match finish(runs_until) {
+        Some(rest) => (runs_until, rest),
+        None => {
+            let (mut kept, mut rest) = (0, finish(0)?);
+            let mut unfinishable = runs_until;
+            while unfinishable - kept > 1 {
+                let middle = (kept + unfinishable) / 2;
+                match finish(middle) {
+                    Some(middle_rest) => (kept, rest) = (middle, middle_rest),
+                    None => unfinishable = middle,
+                }
+            }
+            (kept, rest)
+        }
+    };
+
+    Some(match rest.first() {
+        _ if kept < commands.len() => Hint::Wrong(kept),
+        Some(command) => Hint::Next(command.clone()),
+        None => Hint::Run,
+    })
+}
+
+fn program(nodes: &[Node], mut index: usize) -> Vec<HarvestorCommands> {
+    let mut commands = vec![];
+    while let Some((parent, command)) = &nodes[index].parent {
+        commands.push(command.clone());
+        index = *parent;
+    }
+    commands.reverse();
+
+    commands
+}
+
+#[cfg(test)]
+use crate::level::parse_level;
+#[cfg(test)]
+use crate::program::Program;
+#[cfg(test)]
+use crate::puzzle::{PuzzleSeed, HARVESTOR_START, HARVESTOR_START_FACING};
+#[cfg(test)]
+use bevy::math::UVec2;
+
+#[test]
+fn solves_generated_puzzles() {
+    for seed in 0..50 {
+        let mut rng = PuzzleSeed(seed).rng();
+        let mut puzzle = Puzzle::generate(
+            &mut rng,
+            HARVESTOR_START,
+            HARVESTOR_START_FACING,
+            UVec2::new(6, 6),
+            10,
+            15,
+        );
+        puzzle.place_obstacles(&mut rng, 3);
+
+        let solution = solve(
+            &puzzle.target,
+            &puzzle.field_spec(),
+            puzzle.start,
+            &puzzle.facing,
+            &puzzle.controls,
+        )
+        .unwrap();
+
+        assert!(solution.len() <= puzzle.solution.size());
+        assert!(Puzzle::new(
+            puzzle.size,
+            puzzle.start,
+            puzzle.facing.clone(),
+            puzzle.target.clone(),
+            puzzle.obstacles.clone(),
+            Program::from_moves(&solution),
+            puzzle.header_width,
+        )
+        .is_ok());
+    }
+}
+
+#[test]
+fn finds_shortest_route() {
+    let level = parse_level(
+        "start: 0 -1 left
+
+        ###
+        #.#
+        ###",
+    )
+    .unwrap();
+    let puzzle = &level.puzzle;
+
+    let solution = solve(
+        &puzzle.target,
+        &puzzle.field_spec(),
+        puzzle.start,
+        &puzzle.facing,
+        &puzzle.controls,
+    )
+    .unwrap();
+
+    assert_eq!(solution.len(), 8);
+
+    let level = parse_level("start: 0 -1 left\n\n#.#").unwrap();
+    let puzzle = &level.puzzle;
+    assert_eq!(
+        solve(
+            &puzzle.target,
+            &puzzle.field_spec(),
+            puzzle.start,
+            &puzzle.facing,
+            &puzzle.controls,
+        ),
+        None
+    );
+}
+
+#[test]
+fn hints_follow_the_entered_commands() {
+    use HarvestorCommands::*;
+    let level = parse_level("start: 1 -1 up\n\n##.\n.#.").unwrap();
+    let puzzle = &level.puzzle;
+
+    assert_eq!(hint(puzzle, &[]), Some(Hint::Next(Up)));
+    assert_eq!(hint(puzzle, &[Up, Up]), Some(Hint::Next(Left)));
+    assert_eq!(hint(puzzle, &[Up, Up, Left]), Some(Hint::Run));
+    // harvests a square that should stand
+    assert_eq!(hint(puzzle, &[Up, Right, Up]), Some(Hint::Wrong(1)));
+    assert_eq!(
+        hint(puzzle, &[Up, Right, Up, Left, Left, Down]),
+        Some(Hint::Wrong(1))
+    );
+    // drives off the field
+    assert_eq!(hint(puzzle, &[Up, Down, Up]), Some(Hint::Wrong(1)));
+}
+
+#[test]
+fn relative_controls_count_turns() {
+    // both ways round take four moves, but going up the left side first turns only twice
+    let level = parse_level("start: 0 -1 up\ncontrols: relative\n\n##\n##").unwrap();
+
+    assert_eq!(level.par, 6);
+}
@@ -1,3 +1,4 @@
+use crate::program::Procedure;
 use bevy::prelude::*;
 
 pub struct UIPlugin;
@@ -8,7 +9,9 @@ impl Plugin for UIPlugin {
             .init_resource::<FontHandle>()
             .add_startup_system(setup_font)
             .add_startup_system(setup_countdown.after(setup_font))
-            .add_startup_system(setup_commands)
+            .add_startup_system(setup_commands.after(setup_font))
+            .add_startup_system(setup_seed_text.after(setup_font))
+            .add_startup_system(setup_playback_speed_text.after(setup_font))
             .add_startup_system(setup_win_lose_text.after(setup_font));
     }
 }
@@ -16,6 +19,10 @@ impl Plugin for UIPlugin {
 #[derive(Default)]
 pub struct ArrowImage {
     pub(crate) handle: Handle<Image>,
+    pub(crate) turn_left: Handle<Image>,
+    pub(crate) turn_right: Handle<Image>,
+    /// A raised header, turned upside down it's lowered
+    pub(crate) header: Handle<Image>,
 }
 
 #[derive(Default)]
@@ -113,16 +120,89 @@ fn setup_countdown(mut commands: Commands, font: Res<FontHandle>) {
         });
 }
 
+#[derive(Component)]
+pub struct SeedTextMarker;
+
+fn setup_seed_text(mut commands: Commands, font: Res<FontHandle>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::FlexStart,
+                align_items: AlignItems::FlexEnd,
+                position_type: PositionType::Absolute,
+                padding: UiRect::all(Val::Px(16.0)),
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: font.handle.clone(),
+                        font_size: 24.0,
+                        color: Color::WHITE,
+                    },
+                ))
+                .insert(SeedTextMarker);
+        });
+}
+
+/// Shows the [`crate::harvestor::PlaybackSpeed`]
+#[derive(Component)]
+pub struct PlaybackSpeedTextMarker;
+
+fn setup_playback_speed_text(mut commands: Commands, font: Res<FontHandle>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::FlexStart,
+                align_items: AlignItems::FlexStart,
+                position_type: PositionType::Absolute,
+                padding: UiRect::all(Val::Px(16.0)),
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: font.handle.clone(),
+                        font_size: 24.0,
+                        color: Color::WHITE,
+                    },
+                ))
+                .insert(PlaybackSpeedTextMarker);
+        });
+}
+
 #[derive(Component)]
 pub struct CommandsContainerMarker;
 
+/// Holds the commands of a procedure, in a row above the main commands
+#[derive(Component)]
+pub struct ProcedureContainerMarker(pub Procedure);
+
 fn setup_commands(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut arrow_image: ResMut<ArrowImage>,
+    font: Res<FontHandle>,
 ) {
     let handle = asset_server.load("arrow.png");
     arrow_image.handle = handle;
+    arrow_image.turn_left = asset_server.load("turn_left.png");
+    arrow_image.turn_right = asset_server.load("turn_right.png");
+    arrow_image.header = asset_server.load("header.png");
     commands
         .spawn_bundle(NodeBundle {
             style: Style {
@@ -151,6 +231,51 @@ fn setup_commands(
                     ..default()
                 })
                 .insert(CommandsContainerMarker);
+
+            for procedure in Procedure::ALL {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Row,
+                            align_items: AlignItems::Center,
+                            padding: UiRect {
+                                left: Val::Px(16.0),
+                                right: Val::Px(16.0),
+                                top: Val::Px(0.0),
+                                bottom: Val::Px(8.0),
+                            },
+                            ..default()
+                        },
+                        color: Color::NONE.into(),
+                        ..default()
+                    })
+                    .with_children(|row| {
+                        row.spawn_bundle(
+                            TextBundle::from_section(
+                                procedure.name(),
+                                TextStyle {
+                                    font: font.handle.clone(),
+                                    font_size: 32.0,
+                                    color: Color::WHITE,
+                                },
+                            )
+                            .with_style(Style {
+                                margin: UiRect::all(Val::Px(5.0)),
+                                ..default()
+                            }),
+                        );
+                        row.spawn_bundle(NodeBundle {
+                            style: Style {
+                                flex_direction: FlexDirection::Row,
+                                align_items: AlignItems::Center,
+                                ..default()
+                            },
+                            color: Color::NONE.into(),
+                            ..default()
+                        })
+                        .insert(ProcedureContainerMarker(procedure));
+                    });
+            }
         });
 }
 